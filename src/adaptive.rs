@@ -0,0 +1,285 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet, LinkedList, VecDeque},
+    mem::{self, ManuallyDrop},
+    ops::{Deref, DerefMut},
+    rc::Rc,
+    sync::Arc,
+};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::global_garbage_can;
+
+#[cfg(doc)]
+use crate::{Builder, DeferDrop, GarbageCan};
+
+/// A rough estimate of how expensive it is to drop a value.
+///
+/// The cost is measured in arbitrary units; roughly, the number of
+/// allocations that will be freed, or elements that will be visited, when the
+/// value is dropped. [`AdaptiveDeferDrop`] and
+/// [`GarbageCan::throw_away_if_costly`] compare it to a threshold (see
+/// [`Builder::cost_threshold`]) to decide whether to defer the drop, or just
+/// do it inline.
+///
+/// Estimating the cost should itself be cheap; the implementations for
+/// collections use their length, rather than visiting every element.
+pub trait DropCost {
+    /// Estimate the cost of dropping this value.
+    fn estimated_cost(&self) -> usize;
+}
+
+/// The cost of dropping a collection with `len` elements of type `T`. If
+/// `T` doesn't need to be dropped, freeing the collection is a single
+/// deallocation.
+#[inline]
+fn collection_cost<T>(len: usize) -> usize {
+    match mem::needs_drop::<T>() {
+        true => len,
+        false => 1,
+    }
+}
+
+impl DropCost for String {
+    #[inline]
+    fn estimated_cost(&self) -> usize {
+        1
+    }
+}
+
+impl<T: DropCost + ?Sized> DropCost for Box<T> {
+    #[inline]
+    fn estimated_cost(&self) -> usize {
+        T::estimated_cost(self) + 1
+    }
+}
+
+impl<T: DropCost + ?Sized> DropCost for Arc<T> {
+    /// An `Arc` is only expensive to drop if this is the last reference to it.
+    #[inline]
+    fn estimated_cost(&self) -> usize {
+        match Arc::strong_count(self) {
+            1 => T::estimated_cost(self) + 1,
+            _ => 0,
+        }
+    }
+}
+
+impl<T: DropCost + ?Sized> DropCost for Rc<T> {
+    /// An `Rc` is only expensive to drop if this is the last reference to it.
+    #[inline]
+    fn estimated_cost(&self) -> usize {
+        match Rc::strong_count(self) {
+            1 => T::estimated_cost(self) + 1,
+            _ => 0,
+        }
+    }
+}
+
+impl<T: DropCost> DropCost for Option<T> {
+    #[inline]
+    fn estimated_cost(&self) -> usize {
+        self.as_ref().map_or(0, T::estimated_cost)
+    }
+}
+
+impl<T> DropCost for Vec<T> {
+    #[inline]
+    fn estimated_cost(&self) -> usize {
+        collection_cost::<T>(self.len())
+    }
+}
+
+impl<T> DropCost for Box<[T]> {
+    #[inline]
+    fn estimated_cost(&self) -> usize {
+        collection_cost::<T>(self.len())
+    }
+}
+
+impl<T> DropCost for VecDeque<T> {
+    #[inline]
+    fn estimated_cost(&self) -> usize {
+        collection_cost::<T>(self.len())
+    }
+}
+
+impl<T> DropCost for BinaryHeap<T> {
+    #[inline]
+    fn estimated_cost(&self) -> usize {
+        collection_cost::<T>(self.len())
+    }
+}
+
+impl<K, V, S> DropCost for HashMap<K, V, S> {
+    #[inline]
+    fn estimated_cost(&self) -> usize {
+        collection_cost::<(K, V)>(self.len())
+    }
+}
+
+impl<T, S> DropCost for HashSet<T, S> {
+    #[inline]
+    fn estimated_cost(&self) -> usize {
+        collection_cost::<T>(self.len())
+    }
+}
+
+// Linked lists and B-trees are made of many separate allocations, so they're
+// expensive to drop even if their elements aren't.
+
+impl<T> DropCost for LinkedList<T> {
+    #[inline]
+    fn estimated_cost(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<K, V> DropCost for BTreeMap<K, V> {
+    #[inline]
+    fn estimated_cost(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<T> DropCost for BTreeSet<T> {
+    #[inline]
+    fn estimated_cost(&self) -> usize {
+        self.len()
+    }
+}
+
+/// Wrapper type that, when dropped, decides whether to send the inner value
+/// to the global background thread, based on its estimated [`DropCost`].
+///
+/// Deferring a drop isn't free: it costs a trip through a channel (and an
+/// allocation, for values larger than three words). For cheap values, it's
+/// faster to just drop them inline. An `AdaptiveDeferDrop` only defers the
+/// drop if the value's [`estimated_cost`][DropCost::estimated_cost] is at
+/// least the global garbage can's [cost threshold][Builder::cost_threshold].
+/// Otherwise it behaves just like a [`DeferDrop`].
+///
+/// # Example
+///
+/// ```
+/// use defer_drop::AdaptiveDeferDrop;
+///
+/// // This is cheap to drop, so it's dropped inline
+/// let small: AdaptiveDeferDrop<Vec<String>> = AdaptiveDeferDrop::new(vec![]);
+/// drop(small);
+///
+/// // This is expensive to drop, so it's sent to the background thread
+/// let large = AdaptiveDeferDrop::new(vec![String::from("Hello"); 100_000]);
+/// drop(large);
+/// ```
+#[repr(transparent)]
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct AdaptiveDeferDrop<T: DropCost + Send + 'static> {
+    inner: ManuallyDrop<T>,
+}
+
+impl<T: DropCost + Send + 'static> AdaptiveDeferDrop<T> {
+    /// Create a new `AdaptiveDeferDrop` value.
+    #[inline]
+    pub fn new(value: T) -> Self {
+        AdaptiveDeferDrop {
+            inner: ManuallyDrop::new(value),
+        }
+    }
+
+    /// Unwrap the `AdaptiveDeferDrop`, returning the inner value. This has
+    /// the effect of cancelling the deferred drop behavior; ownership of the
+    /// inner value is transferred to the caller.
+    pub fn into_inner(mut this: Self) -> T {
+        let value = unsafe { ManuallyDrop::take(&mut this.inner) };
+        mem::forget(this);
+        value
+    }
+}
+
+impl<T: DropCost + Send + 'static> Drop for AdaptiveDeferDrop<T> {
+    fn drop(&mut self) {
+        global_garbage_can().throw_away_if_costly(unsafe { ManuallyDrop::take(&mut self.inner) });
+    }
+}
+
+impl<T: DropCost + Send + 'static> From<T> for AdaptiveDeferDrop<T> {
+    #[inline]
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<T: DropCost + Send + 'static> AsRef<T> for AdaptiveDeferDrop<T> {
+    #[inline]
+    fn as_ref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T: DropCost + Send + 'static> AsMut<T> for AdaptiveDeferDrop<T> {
+    #[inline]
+    fn as_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+impl<T: DropCost + Send + 'static> Deref for AdaptiveDeferDrop<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl<T: DropCost + Send + 'static> DerefMut for AdaptiveDeferDrop<T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T: DropCost + Serialize + Send + 'static> Serialize for AdaptiveDeferDrop<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.as_ref().serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T: DropCost + Deserialize<'de> + Send + 'static> Deserialize<'de>
+    for AdaptiveDeferDrop<T>
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        T::deserialize(deserializer).map(Self::new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::DropCost;
+
+    #[test]
+    fn test_estimated_cost() {
+        assert_eq!(vec![1; 1000].estimated_cost(), 1);
+        assert_eq!(vec![String::new(); 1000].estimated_cost(), 1000);
+        assert_eq!(Some(Box::new(vec![String::new(); 10])).estimated_cost(), 11);
+        assert_eq!(
+            (0..100)
+                .map(|i| (i, i))
+                .collect::<BTreeMap<_, _>>()
+                .estimated_cost(),
+            100
+        );
+    }
+}
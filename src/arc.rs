@@ -0,0 +1,198 @@
+use std::{
+    borrow::Borrow,
+    mem::{self, ManuallyDrop},
+    ops::Deref,
+    sync::Arc,
+};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::global_garbage_can;
+
+#[cfg(doc)]
+use crate::DeferDrop;
+
+/// A shared reference to a value, which is dropped in the global background
+/// thread once the last reference is gone.
+///
+/// Wrapping an [`Arc`] in a [`DeferDrop`] would send every clone to the
+/// background thread when it's dropped, even though dropping a clone is only
+/// a reference count decrement. A `DeferDropArc` only defers the drop that
+/// actually destroys the value; dropping any other clone just decrements the
+/// count, inline.
+///
+/// # Example
+///
+/// ```
+/// use defer_drop::DeferDropArc;
+///
+/// let shared = DeferDropArc::new(vec![String::from("Hello"); 1000]);
+/// let clone = shared.clone();
+///
+/// // This is just a decrement
+/// drop(shared);
+///
+/// // This sends the vector to the background thread
+/// drop(clone);
+/// ```
+#[repr(transparent)]
+#[derive(Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct DeferDropArc<T: Send + Sync + 'static> {
+    inner: ManuallyDrop<Arc<T>>,
+}
+
+impl<T: Send + Sync + 'static> DeferDropArc<T> {
+    /// Create a new `DeferDropArc` value.
+    #[inline]
+    pub fn new(value: T) -> Self {
+        Self::from_arc(Arc::new(value))
+    }
+
+    /// Wrap an existing [`Arc`]. Only drops of this `DeferDropArc` (and its
+    /// clones) are deferred; if some other clone of the `Arc` turns out to be
+    /// the last reference, the value is dropped wherever that one is.
+    #[inline]
+    pub fn from_arc(arc: Arc<T>) -> Self {
+        DeferDropArc {
+            inner: ManuallyDrop::new(arc),
+        }
+    }
+
+    /// Unwrap the `DeferDropArc`, returning the inner [`Arc`]. This has the
+    /// effect of cancelling the deferred drop behavior for this reference.
+    pub fn into_arc(mut this: Self) -> Arc<T> {
+        let arc = unsafe { ManuallyDrop::take(&mut this.inner) };
+        mem::forget(this);
+        arc
+    }
+
+    /// Get the number of strong references to the value. See
+    /// [`Arc::strong_count`].
+    #[inline]
+    pub fn strong_count(this: &Self) -> usize {
+        Arc::strong_count(&this.inner)
+    }
+
+    /// Check if two `DeferDropArc`s point to the same value. See
+    /// [`Arc::ptr_eq`].
+    #[inline]
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        Arc::ptr_eq(&this.inner, &other.inner)
+    }
+}
+
+impl<T: Send + Sync + 'static> Drop for DeferDropArc<T> {
+    fn drop(&mut self) {
+        let arc = unsafe { ManuallyDrop::take(&mut self.inner) };
+
+        match Arc::strong_count(&arc) {
+            // This is the last reference, so send the whole `Arc`, which is
+            // cheaper than moving the value out of it.
+            1 => global_garbage_can().throw_away(arc),
+
+            // Otherwise, this is just a decrement; unless the other
+            // references were dropped in the meantime, in which case this is
+            // the last one after all.
+            _ => {
+                if let Some(value) = Arc::into_inner(arc) {
+                    global_garbage_can().throw_away(value);
+                }
+            }
+        }
+    }
+}
+
+impl<T: Send + Sync + 'static> Clone for DeferDropArc<T> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self::from_arc(Arc::clone(&self.inner))
+    }
+}
+
+impl<T: Send + Sync + 'static> From<T> for DeferDropArc<T> {
+    #[inline]
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<T: Send + Sync + 'static> From<Arc<T>> for DeferDropArc<T> {
+    #[inline]
+    fn from(arc: Arc<T>) -> Self {
+        Self::from_arc(arc)
+    }
+}
+
+impl<T: Send + Sync + 'static> AsRef<T> for DeferDropArc<T> {
+    #[inline]
+    fn as_ref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T: Send + Sync + 'static> Borrow<T> for DeferDropArc<T> {
+    #[inline]
+    fn borrow(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T: Send + Sync + 'static> Deref for DeferDropArc<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T: Serialize + Send + Sync + 'static> Serialize for DeferDropArc<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.as_ref().serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T: Deserialize<'de> + Send + Sync + 'static> Deserialize<'de> for DeferDropArc<T> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        T::deserialize(deserializer).map(Self::new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossbeam_channel as channel;
+    use std::{thread, time::Duration};
+
+    use super::DeferDropArc;
+    use crate::test_util::ThreadReporter;
+
+    #[test]
+    fn test_last_reference() {
+        let (sender, receiver) = channel::bounded(1);
+        let shared = DeferDropArc::new(ThreadReporter { chan: sender });
+        let clone = shared.clone();
+
+        assert!(DeferDropArc::ptr_eq(&shared, &clone));
+        assert_eq!(DeferDropArc::strong_count(&shared), 2);
+
+        drop(shared);
+        crate::flush();
+        assert!(receiver.try_recv().is_err(), "value was dropped too soon");
+
+        drop(clone);
+
+        match receiver.recv_timeout(Duration::from_secs(1)) {
+            Ok(id) => assert_ne!(id, thread::current().id()),
+            Err(_) => panic!("value wasn't dropped within one second of being dropped"),
+        }
+    }
+}
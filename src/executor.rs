@@ -0,0 +1,134 @@
+use std::{rc::Rc, sync::Arc};
+
+use crate::garbage::Garbage;
+
+#[cfg(doc)]
+use crate::{DeferDropIn, GarbageCan};
+
+/// Somewhere to send deferred values to be destroyed.
+///
+/// [`GarbageCan`] is the standard implementation, which sends values to its
+/// background threads; [`CurrentThread`] drops them right away. Implement
+/// this trait to route deferred drops somewhere else, like your own job
+/// system or the end of a game engine's frame, and use [`DeferDropIn`] to
+/// defer values to it.
+///
+/// # Example
+///
+/// ```
+/// use std::cell::RefCell;
+/// use defer_drop::{DeferDropIn, DropExecutor, Garbage};
+///
+/// #[derive(Default)]
+/// struct FrameEnd {
+///     garbage: RefCell<Vec<Garbage>>,
+/// }
+///
+/// impl DropExecutor for FrameEnd {
+///     fn execute(&self, garbage: Garbage) {
+///         self.garbage.borrow_mut().push(garbage);
+///     }
+/// }
+///
+/// let frame_end = FrameEnd::default();
+///
+/// let value = DeferDropIn::new(vec![1, 2, 3], &frame_end);
+/// drop(value);
+/// assert_eq!(frame_end.garbage.borrow().len(), 1);
+///
+/// // Later, once the frame is done
+/// frame_end.garbage.borrow_mut().clear();
+/// ```
+pub trait DropExecutor {
+    /// Destroy `garbage`, either now or at some later point. Dropping the
+    /// [`Garbage`] drops the value inside it.
+    fn execute(&self, garbage: Garbage);
+}
+
+impl<E: DropExecutor + ?Sized> DropExecutor for &E {
+    #[inline]
+    fn execute(&self, garbage: Garbage) {
+        E::execute(self, garbage)
+    }
+}
+
+impl<E: DropExecutor + ?Sized> DropExecutor for Box<E> {
+    #[inline]
+    fn execute(&self, garbage: Garbage) {
+        E::execute(self, garbage)
+    }
+}
+
+impl<E: DropExecutor + ?Sized> DropExecutor for Rc<E> {
+    #[inline]
+    fn execute(&self, garbage: Garbage) {
+        E::execute(self, garbage)
+    }
+}
+
+impl<E: DropExecutor + ?Sized> DropExecutor for Arc<E> {
+    #[inline]
+    fn execute(&self, garbage: Garbage) {
+        E::execute(self, garbage)
+    }
+}
+
+/// A [`DropExecutor`] that drops values right away, on the current thread,
+/// as though they had never been deferred.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct CurrentThread;
+
+impl DropExecutor for CurrentThread {
+    #[inline]
+    fn execute(&self, garbage: Garbage) {
+        drop(garbage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, sync::Arc};
+
+    use super::{CurrentThread, DropExecutor};
+    use crate::{garbage::Garbage, DeferDropIn};
+
+    #[derive(Default)]
+    struct Collector {
+        garbage: RefCell<Vec<Garbage>>,
+    }
+
+    impl DropExecutor for Collector {
+        fn execute(&self, garbage: Garbage) {
+            self.garbage.borrow_mut().push(garbage);
+        }
+    }
+
+    #[test]
+    fn test_current_thread() {
+        let value = Arc::new(());
+        let deferred = DeferDropIn::new(value.clone(), &CurrentThread);
+
+        assert_eq!(Arc::strong_count(&value), 2);
+        drop(deferred);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn test_custom_executor() {
+        let collector = Collector::default();
+        let value = Arc::new(());
+
+        // Through a trait object, to make sure that works too
+        let executor: &dyn DropExecutor = &collector;
+        drop(DeferDropIn::new(value.clone(), executor));
+
+        assert_eq!(Arc::strong_count(&value), 2);
+        assert_eq!(
+            collector.garbage.borrow()[0].type_name(),
+            std::any::type_name::<Arc<()>>()
+        );
+
+        collector.garbage.borrow_mut().clear();
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+}
@@ -0,0 +1,168 @@
+use std::{
+    any, fmt,
+    mem::{self, MaybeUninit},
+    ptr,
+};
+
+/// The number of words of storage in a [`Garbage`]. Values that fit (like a
+/// `Vec` or a `String`, which are three words each) are stored inline; larger
+/// ones are boxed.
+const SLOT_WORDS: usize = 3;
+
+type Slot = MaybeUninit<[usize; SLOT_WORDS]>;
+
+/// A type-erased value to drop. This is what a [`DropExecutor`] is given to
+/// destroy; dropping the `Garbage` drops the value inside it.
+///
+/// Small values are stored inline, along with a function pointer that drops
+/// them, so that deferring them doesn't cost a heap allocation. Values that
+/// are too large, or too strictly aligned, to fit in the slot are boxed, and
+/// the box is stored in the slot instead.
+///
+/// [`DropExecutor`]: crate::DropExecutor
+pub struct Garbage {
+    slot: Slot,
+    drop_fn: unsafe fn(*mut Slot),
+    type_name: &'static str,
+}
+
+// Safety: `Garbage::new` only accepts `Send` values.
+unsafe impl Send for Garbage {}
+
+/// Drop the `T` stored in `slot`.
+///
+/// # Safety
+///
+/// `slot` must contain an initialized `T`, which must not be used again.
+unsafe fn drop_slot<T>(slot: *mut Slot) {
+    ptr::drop_in_place(slot as *mut T)
+}
+
+/// Check if a `T` can be stored directly in a [`Slot`].
+const fn fits<T>() -> bool {
+    mem::size_of::<T>() <= mem::size_of::<Slot>() && mem::align_of::<T>() <= mem::align_of::<Slot>()
+}
+
+impl Garbage {
+    /// Wrap a value, so that it can be dropped without knowing its type.
+    #[inline]
+    pub fn new<T: Send + 'static>(value: T) -> Self {
+        Self::with_type_name(value, any::type_name::<T>())
+    }
+
+    /// Wrap a value, but report it as `type_name`. This is for internal
+    /// wrappers (like the one that completes a
+    /// [`DropHandle`][crate::DropHandle]), which should be reported as the
+    /// type that they wrap.
+    pub(crate) fn with_type_name<T: Send + 'static>(value: T, type_name: &'static str) -> Self {
+        match fits::<T>() {
+            true => Self::store(value, type_name),
+            false => Self::store(Box::new(value), type_name),
+        }
+    }
+
+    /// Store `value` in the slot. The caller must check that it fits.
+    fn store<U>(value: U, type_name: &'static str) -> Self {
+        debug_assert!(fits::<U>());
+
+        let mut slot = Slot::uninit();
+
+        // Safety: the caller checked that a `U` fits in the slot, both in
+        // size and alignment.
+        unsafe { ptr::write(slot.as_mut_ptr() as *mut U, value) };
+
+        Self {
+            slot,
+            drop_fn: drop_slot::<U>,
+            type_name,
+        }
+    }
+
+    /// The name of the type of the value, as reported by
+    /// [`std::any::type_name`].
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+}
+
+impl fmt::Debug for Garbage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Garbage")
+            .field("type_name", &self.type_name)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for Garbage {
+    fn drop(&mut self) {
+        // Safety: the slot was initialized with the type that `drop_fn`
+        // expects, and this is the only place it's dropped.
+        unsafe { (self.drop_fn)(&mut self.slot) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        any,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    use super::{fits, Garbage};
+
+    /// Counts the number of times it's dropped.
+    struct DropCounter<T> {
+        _padding: T,
+        count: Arc<AtomicUsize>,
+    }
+
+    impl<T> Drop for DropCounter<T> {
+        fn drop(&mut self) {
+            self.count.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn check_dropped_once<T: Send + 'static>(padding: T) {
+        let count = Arc::new(AtomicUsize::new(0));
+
+        let garbage = Garbage::new(DropCounter {
+            _padding: padding,
+            count: count.clone(),
+        });
+
+        // Move it around a bit
+        let garbage = vec![garbage].pop().unwrap();
+
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+        drop(garbage);
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_inline() {
+        assert!(fits::<Vec<i32>>());
+        assert!(fits::<String>());
+        assert!(fits::<DropCounter<u64>>());
+
+        check_dropped_once(());
+        check_dropped_once(1u64);
+    }
+
+    #[test]
+    fn test_boxed() {
+        assert!(!fits::<DropCounter<[u64; 8]>>());
+
+        check_dropped_once([1u64; 8]);
+    }
+
+    #[test]
+    fn test_type_name() {
+        assert_eq!(
+            Garbage::new(String::new()).type_name(),
+            any::type_name::<String>()
+        );
+    }
+}
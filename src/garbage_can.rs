@@ -0,0 +1,2040 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::VecDeque,
+    fmt, io, mem,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Condvar, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, Weak,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crossbeam_channel::{self as channel, Receiver, RecvError, Sender, TryRecvError, TrySendError};
+
+use crate::{
+    garbage::Garbage,
+    incremental::Incremental,
+    stats::{Counters, Profile, Stats, TypeStats},
+    timers::Timers,
+    Builder, DeferDropIn, DropCost, DropExecutor, IncrementalDrop, Overflow, PanicPolicy, Priority,
+};
+
+#[cfg(feature = "test-executor")]
+use crate::testing;
+
+#[cfg(doc)]
+use crate::DeferDrop;
+
+/// A message sent through the garbage queue.
+enum Message {
+    /// A value to drop.
+    Garbage(Garbage),
+
+    /// Several values to drop, batched up by a sending thread. See
+    /// [`Builder::batch_size`].
+    Batch(Vec<Garbage>),
+
+    /// A value to drop a slice at a time.
+    Incremental(Incremental),
+
+    /// A value to drop once its deadline has passed.
+    Delayed(Instant, Garbage),
+
+    /// A barrier; whichever background thread takes it should arrive at the
+    /// latch once everything that was enqueued before it has been taken from
+    /// the queue.
+    Flush(PendingFlush),
+
+    /// Sent to each background thread in turn, on its own control queue,
+    /// once a flush message has been caught up with. It should arrive at the
+    /// latch once it's finished with everything it took before.
+    Checkpoint(Arc<Latch>),
+}
+
+/// A flush that a background thread has picked up, but hasn't caught up
+/// with yet.
+struct PendingFlush {
+    latch: Arc<Latch>,
+
+    // The number of values still to take from the normal priority, spill and
+    // low priority queues, which were in them when the flush was requested.
+    backlog: [usize; 3],
+}
+
+/// Latch used to flush a garbage can. The background threads count down
+/// without waiting, so that a flush never holds them up.
+struct Latch {
+    remaining: Mutex<usize>,
+    condvar: Condvar,
+}
+
+impl Latch {
+    fn new(count: usize) -> Self {
+        Self {
+            remaining: Mutex::new(count),
+            condvar: Condvar::new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, usize> {
+        self.remaining
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Count down. If the flush was given up on, this does nothing.
+    fn arrive(&self) {
+        let mut remaining = self.lock();
+        *remaining = remaining.saturating_sub(1);
+        self.condvar.notify_all();
+    }
+
+    /// Wait for all of the background threads to arrive. Returns false if
+    /// the deadline passed first.
+    fn wait(&self, deadline: Option<Instant>) -> bool {
+        let mut remaining = self.lock();
+
+        while *remaining > 0 {
+            remaining = match deadline {
+                None => self
+                    .condvar
+                    .wait(remaining)
+                    .unwrap_or_else(PoisonError::into_inner),
+                Some(deadline) => {
+                    let timeout = match deadline.checked_duration_since(Instant::now()) {
+                        Some(timeout) => timeout,
+                        None => return false,
+                    };
+
+                    self.condvar
+                        .wait_timeout(remaining, timeout)
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0
+                }
+            };
+        }
+
+        true
+    }
+}
+
+/// The sending halves of one of a garbage can's queues. Each queue is made
+/// up of a channel for each priority, plus the spill channel, if any.
+struct Senders {
+    by_priority: [Sender<Message>; Priority::COUNT],
+    spill: Option<Sender<Message>>,
+}
+
+/// The parts of a garbage can that are needed to send garbage to it. These
+/// are shared with the local batches of the threads that send to it, so that
+/// a batch can be sent when its thread exits.
+struct Shared {
+    // This is `None` after the garbage can has been shut down. Otherwise,
+    // there's one queue shared by all of the background threads, or (in
+    // ordered mode) one queue per thread.
+    queues: RwLock<Option<Vec<Senders>>>,
+    overflow: Overflow,
+    counters: Arc<Counters>,
+}
+
+impl Shared {
+    fn queues(&self) -> RwLockReadGuard<'_, Option<Vec<Senders>>> {
+        self.queues.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Send a message containing `count` values to the background threads,
+    /// or drop it inline if that isn't possible.
+    fn send(&self, message: Message, count: u64, priority: Priority) {
+        self.send_with(message, count, priority, self.overflow)
+    }
+
+    /// Like [`send`][Shared::send], but with a different overflow policy
+    /// than the garbage can's own.
+    fn send_with(&self, message: Message, count: u64, priority: Priority, overflow: Overflow) {
+        // If sending fails, either the queue is full (and the overflow policy
+        // says to drop inline) or the background threads are gone. Either
+        // way, we get the message back, and drop it here once we've released
+        // the lock.
+        let rejected = {
+            let queues = self.queues();
+            let queues = match queues.as_ref() {
+                Some(queues) => queues,
+                None => {
+                    // The garbage can was shut down
+                    self.counters.record_inline(count);
+                    return;
+                }
+            };
+
+            let senders = match queues.len() {
+                1 => &queues[0],
+                len => &queues[QUEUE.with(|&queue| queue) % len],
+            };
+
+            let sender = &senders.by_priority[priority.index()];
+
+            self.counters.record_send(count);
+
+            match overflow {
+                Overflow::Block => sender.send(message).err().map(|err| err.0),
+                Overflow::DropInline => {
+                    sender.try_send(message).err().map(TrySendError::into_inner)
+                }
+                Overflow::Spill => match (sender.try_send(message), &senders.spill) {
+                    (Err(TrySendError::Full(message)), Some(spill)) => {
+                        spill.send(message).err().map(|err| err.0)
+                    }
+                    (result, _) => result.err().map(TrySendError::into_inner),
+                },
+            }
+        };
+
+        match rejected {
+            None => self.counters.record_sent(),
+            Some(message) => {
+                self.counters.record_rejected(count);
+                drop(message);
+            }
+        }
+    }
+}
+
+/// Values that this thread has thrown away, waiting to be sent to a garbage
+/// can as a single batch.
+struct LocalBatch {
+    id: usize,
+    shared: Weak<Shared>,
+    garbage: Vec<Garbage>,
+}
+
+impl LocalBatch {
+    /// Send the batch to its garbage can. If the garbage can is gone, the
+    /// values are dropped here instead.
+    fn send(self) {
+        if let Some(shared) = self.shared.upgrade() {
+            let count = self.garbage.len() as u64;
+            shared.send(Message::Batch(self.garbage), count, Priority::Normal);
+        }
+    }
+}
+
+/// All of this thread's local batches, one for each garbage can that it has
+/// unsent garbage for. They're sent when the thread exits.
+struct LocalBatches(Vec<LocalBatch>);
+
+impl LocalBatches {
+    /// Remove the batch for a garbage can, if there is one.
+    fn take(&mut self, id: usize) -> Option<LocalBatch> {
+        let index = self.0.iter().position(|batch| batch.id == id)?;
+        Some(self.0.swap_remove(index))
+    }
+}
+
+impl Drop for LocalBatches {
+    fn drop(&mut self) {
+        self.0.drain(..).for_each(LocalBatch::send);
+    }
+}
+
+/// Source of unique IDs for garbage cans.
+static NEXT_GARBAGE_CAN_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Source of queue assignments for sending threads, in ordered mode.
+static NEXT_QUEUE: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// If this is a background thread, the ID of its garbage can.
+    static CURRENT_GARBAGE_CAN: Cell<Option<usize>> = const { Cell::new(None) };
+
+    /// The queue that this thread sends to, in ordered mode.
+    static QUEUE: usize = NEXT_QUEUE.fetch_add(1, Ordering::Relaxed);
+
+    /// Garbage waiting to be sent, if batching is enabled.
+    static LOCAL_BATCHES: RefCell<LocalBatches> = const { RefCell::new(LocalBatches(Vec::new())) };
+}
+
+/// A set of background threads that drop values sent to them.
+///
+/// [`DeferDrop`] uses a global `GarbageCan`, which is created lazily or with
+/// [`Builder::init`]. You can also create your own with
+/// [`GarbageCan::new`] or [`Builder::build`], and send values to it with
+/// [`throw_away`][GarbageCan::throw_away] or
+/// [`defer`][GarbageCan::defer]. This is useful for isolating latency-critical
+/// garbage from bulk garbage produced by other parts of a program.
+///
+/// When a `GarbageCan` is dropped, it closes its queues and waits for its
+/// background threads to finish dropping everything in them.
+///
+/// # Example
+///
+/// ```
+/// use defer_drop::Builder;
+///
+/// let can = Builder::new()
+///     .name("my garbage can")
+///     .build()
+///     .expect("failed to spawn background thread");
+///
+/// let value = can.defer(vec![1, 2, 3]);
+/// assert_eq!(value.len(), 3);
+///
+/// drop(value);
+/// can.flush();
+/// ```
+pub struct GarbageCan {
+    id: usize,
+    shared: Arc<Shared>,
+    threads: usize,
+    cost_threshold: usize,
+    batch_size: usize,
+
+    // Background threads add their replacements to this list if they
+    // restart after a panic.
+    handles: Arc<Mutex<Vec<JoinHandle<()>>>>,
+    profile: Option<Arc<Profile>>,
+    timers: Arc<Timers>,
+
+    // Each background thread's control queue, used for flushing.
+    controls: Vec<Sender<Message>>,
+
+    // In manual mode, there are no background threads; instead, whoever
+    // drains the garbage can uses this.
+    drainer: Option<Mutex<Drainer>>,
+}
+
+impl GarbageCan {
+    /// Create a new `GarbageCan`, with the default configuration. Use a
+    /// [`Builder`] to customize it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the background thread can't be spawned.
+    pub fn new() -> Self {
+        Builder::new()
+            .build()
+            .expect("failed to spawn defer-drop background thread")
+    }
+
+    pub(crate) fn from_builder(config: &Builder) -> io::Result<Self> {
+        let id = NEXT_GARBAGE_CAN_ID.fetch_add(1, Ordering::Relaxed);
+        // In manual mode, the queue only has room once the application
+        // drains it, so a blocked thread could wait forever.
+        let overflow = match config.bound {
+            None => Overflow::Block,
+            Some((_, Overflow::Block)) if config.manual => Overflow::DropInline,
+            Some((_, overflow)) => overflow,
+        };
+
+        // In manual mode, there aren't any background threads to spawn.
+        let threads = match config.manual {
+            true => 0,
+            false => config.threads,
+        };
+
+        let queue_count = match config.ordered && !config.manual {
+            true => config.threads,
+            false => 1,
+        };
+
+        let (queues, receivers): (Vec<_>, Vec<_>) = (0..queue_count)
+            .map(|_| {
+                let channels = Priority::ALL.map(|_| match config.bound {
+                    None => channel::unbounded(),
+                    Some((capacity, _)) => channel::bounded(capacity),
+                });
+
+                let by_priority = channels.clone().map(|(sender, _)| sender);
+                let receivers = channels.map(|(_, receiver)| receiver);
+
+                let (spill, spill_receiver) = match overflow {
+                    Overflow::Spill => {
+                        let (spill, spill_receiver) = channel::unbounded();
+                        (Some(spill), spill_receiver)
+                    }
+                    _ => (None, channel::never()),
+                };
+
+                (Senders { by_priority, spill }, (receivers, spill_receiver))
+            })
+            .unzip();
+
+        let handles: Arc<Mutex<Vec<JoinHandle<()>>>> = Default::default();
+        let counters: Arc<Counters> = Default::default();
+        let timers: Arc<Timers> = Default::default();
+
+        let profile: Option<Arc<Profile>> = match config.profile_types {
+            true => Some(Default::default()),
+            false => None,
+        };
+
+        let worker = |index: usize, control: Receiver<Message>| {
+            let (receivers, spill) = receivers[index % queue_count].clone();
+
+            Worker {
+                id,
+                name: match config.threads {
+                    1 => config.name.clone(),
+                    _ => format!("{} {}", config.name, index),
+                },
+                stack_size: config.stack_size,
+                on_panic: config.on_panic.clone(),
+                receivers,
+                spill,
+                control,
+                slice_size: config.slice_size,
+                handles: handles.clone(),
+                counters: counters.clone(),
+                profile: profile.clone(),
+                timers: timers.clone(),
+                flushes: Default::default(),
+            }
+        };
+
+        // If any of the threads fail to spawn, the queues are dropped when we
+        // return the error, which shuts down the threads that did spawn.
+        let (controls, control_receivers): (Vec<_>, Vec<_>) =
+            (0..threads).map(|_| channel::unbounded()).unzip();
+
+        let spawned = control_receivers
+            .into_iter()
+            .enumerate()
+            .map(|(index, control)| worker(index, control).spawn())
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let drainer = match config.manual {
+            true => Some(Mutex::new(Drainer {
+                worker: worker(0, channel::never()),
+                pending: VecDeque::new(),
+                in_progress: VecDeque::new(),
+            })),
+            false => None,
+        };
+
+        handles
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .extend(spawned);
+
+        Ok(Self {
+            id,
+            shared: Arc::new(Shared {
+                queues: RwLock::new(Some(queues)),
+                overflow,
+                counters,
+            }),
+            threads,
+            cost_threshold: config.cost_threshold,
+            batch_size: config.batch_size,
+            handles,
+            profile,
+            timers,
+            controls,
+            drainer,
+        })
+    }
+
+    /// A garbage can with no background threads, which drops everything
+    /// inline. Used if the global garbage can's threads couldn't be spawned.
+    pub(crate) fn unavailable() -> Self {
+        Self {
+            id: NEXT_GARBAGE_CAN_ID.fetch_add(1, Ordering::Relaxed),
+            shared: Arc::new(Shared {
+                queues: RwLock::new(None),
+                overflow: Overflow::Block,
+                counters: Default::default(),
+            }),
+            threads: 0,
+            cost_threshold: 0,
+            batch_size: 1,
+            handles: Default::default(),
+            profile: None,
+            timers: Default::default(),
+            controls: Vec::new(),
+            drainer: None,
+        }
+    }
+
+    fn is_background_thread(&self) -> bool {
+        CURRENT_GARBAGE_CAN.with(|current| current.get()) == Some(self.id)
+    }
+
+    /// Send a value to the background threads to be dropped.
+    ///
+    /// The value is dropped immediately instead if this is called from one of
+    /// this garbage can's own background threads, or if the background
+    /// threads aren't available (because the garbage can was shut down, or
+    /// they couldn't be spawned, or they were lost to a panic). This never
+    /// panics, unless the value's own destructor does.
+    #[inline]
+    pub fn throw_away<T: Send + 'static>(&self, value: T) {
+        self.throw_away_with_priority(value, Priority::Normal);
+    }
+
+    /// Send a value to the background threads to be dropped, with the given
+    /// [priority][crate::priority]. Values with a higher priority are dropped
+    /// before any values with a lower priority that are still waiting.
+    ///
+    /// Only [`Normal`][Priority::Normal] priority values are
+    /// [batched][Builder::batch_size]; others are sent right away.
+    #[inline]
+    pub fn throw_away_with_priority<T: Send + 'static>(&self, value: T, priority: Priority) {
+        self.throw_away_garbage(Garbage::new(value), priority);
+    }
+
+    /// Send a value that's already been type-erased to the background
+    /// threads.
+    pub(crate) fn throw_away_garbage(&self, garbage: Garbage, priority: Priority) {
+        // Only send to the garbage can if we're not currently in the garbage
+        // can; if we are, just drop it eagerly.
+        if self.is_background_thread() {
+            return;
+        }
+
+        #[cfg(feature = "test-executor")]
+        if let Some(interceptor) = testing::interceptor() {
+            interceptor.take(garbage);
+            return;
+        }
+
+        if self.batch_size <= 1 || priority != Priority::Normal {
+            self.shared.send(Message::Garbage(garbage), 1, priority);
+            return;
+        }
+
+        // Add the value to this thread's batch. If that fills it up, take it
+        // out, so that it can be sent once we've released the thread local.
+        // If the thread local is already gone (because this thread is
+        // exiting), send the value on its own.
+        let mut garbage = Some(garbage);
+
+        let full = LOCAL_BATCHES
+            .try_with(|batches| {
+                let mut batches = batches.borrow_mut();
+                let garbage = garbage.take()?;
+
+                let index = match batches.0.iter().position(|batch| batch.id == self.id) {
+                    Some(index) => index,
+                    None => {
+                        batches.0.push(LocalBatch {
+                            id: self.id,
+                            shared: Arc::downgrade(&self.shared),
+                            garbage: Vec::with_capacity(self.batch_size),
+                        });
+                        batches.0.len() - 1
+                    }
+                };
+
+                let batch = &mut batches.0[index];
+                batch.garbage.push(garbage);
+
+                match batch.garbage.len() >= self.batch_size {
+                    true => Some(batches.0.swap_remove(index)),
+                    false => None,
+                }
+            })
+            .ok()
+            .flatten();
+
+        if let Some(batch) = full {
+            batch.send();
+        }
+
+        if let Some(garbage) = garbage {
+            self.shared.send(Message::Garbage(garbage), 1, priority);
+        }
+    }
+
+    /// Send any values that this thread has batched up for this garbage can
+    /// (see [`Builder::batch_size`]) to the background threads, without
+    /// waiting for the batch to fill up. Batches are also sent when
+    /// [`flush`][GarbageCan::flush] is called, and when the thread exits.
+    pub fn flush_local(&self) {
+        let batch = LOCAL_BATCHES
+            .try_with(|batches| batches.borrow_mut().take(self.id))
+            .ok()
+            .flatten();
+
+        if let Some(batch) = batch {
+            batch.send();
+        }
+    }
+
+    /// Send a value to the background threads to be dropped, but only if its
+    /// [estimated cost][DropCost] is at least this garbage can's
+    /// [cost threshold][Builder::cost_threshold]. Cheaper values are dropped
+    /// immediately, since that's faster than sending them.
+    pub fn throw_away_if_costly<T: DropCost + Send + 'static>(&self, value: T) {
+        if value.estimated_cost() >= self.cost_threshold {
+            self.throw_away(value);
+        }
+    }
+
+    /// Send a value to the background threads, to be dropped once `deadline`
+    /// has passed. Until then, the value is kept alive by the background
+    /// threads. This is useful for things like grace periods, where a value
+    /// needs to outlive any readers that might still be using it.
+    ///
+    /// Delayed values aren't included in [`flush`][GarbageCan::flush]. If
+    /// the garbage can is shut down, or its background threads aren't
+    /// available, they're dropped right away.
+    ///
+    /// If the queue is bounded with [`Overflow::DropInline`], this waits for
+    /// room in the queue when it's full, rather than dropping the value
+    /// before its deadline. In [manual mode][crate::Builder::manual], it
+    /// skips the queue instead.
+    pub fn throw_away_at<T: Send + 'static>(&self, value: T, deadline: Instant) {
+        let garbage = Garbage::new(value);
+
+        // The background threads can't send to themselves, but they can add
+        // the timer directly. They'll pick up the new deadline next time
+        // around.
+        if self.is_background_thread() {
+            self.shared.counters.record_send(1);
+            self.shared.counters.record_sent();
+            self.timers.insert(deadline, garbage);
+            return;
+        }
+
+        #[cfg(feature = "test-executor")]
+        if let Some(interceptor) = testing::interceptor() {
+            interceptor.take(garbage);
+            return;
+        }
+
+        // Dropping a delayed value inline would drop it too early, so wait
+        // for room instead. In manual mode, nothing makes room until the
+        // application drains the queue, so add the timer directly; it's
+        // checked on every drain anyway.
+        let overflow = match self.shared.overflow {
+            Overflow::DropInline if self.drainer.is_some() => {
+                let queues = self.shared.queues();
+
+                if queues.is_some() {
+                    self.shared.counters.record_send(1);
+                    self.shared.counters.record_sent();
+                    self.timers.insert(deadline, garbage);
+                    return;
+                }
+
+                Overflow::DropInline
+            }
+            Overflow::DropInline => Overflow::Block,
+            overflow => overflow,
+        };
+
+        self.shared.send_with(
+            Message::Delayed(deadline, garbage),
+            1,
+            Priority::Normal,
+            overflow,
+        );
+    }
+
+    /// Send a value to the background threads, to be dropped once `delay`
+    /// has passed. See [`throw_away_at`][GarbageCan::throw_away_at] for
+    /// details.
+    #[inline]
+    pub fn throw_away_after<T: Send + 'static>(&self, value: T, delay: Duration) {
+        self.throw_away_at(value, Instant::now() + delay);
+    }
+
+    /// Send a value to the background threads to be dropped a slice at a
+    /// time, with other garbage dropped in between. See [`IncrementalDrop`]
+    /// for details.
+    pub fn throw_away_incremental<T: IncrementalDrop>(&self, value: T) {
+        if self.is_background_thread() {
+            return;
+        }
+
+        #[cfg(feature = "test-executor")]
+        if let Some(interceptor) = testing::interceptor() {
+            interceptor.take(Garbage::new(value));
+            return;
+        }
+
+        // Keep this thread's values in order, if it has any batched up.
+        self.flush_local();
+        self.shared.send(
+            Message::Incremental(Incremental::new(value)),
+            1,
+            Priority::Normal,
+        );
+    }
+
+    /// Send a collection to the background threads, which drop its elements
+    /// in parallel on the rayon thread pool. See
+    /// [`ParallelDeferDrop`][crate::ParallelDeferDrop] for details. Requires
+    /// the `rayon` feature.
+    #[cfg(feature = "rayon")]
+    pub fn throw_away_parallel<T>(&self, collection: T)
+    where
+        T: rayon::iter::IntoParallelIterator + Send + 'static,
+    {
+        self.throw_away_garbage(
+            Garbage::with_type_name(
+                crate::parallel::Parallel::new(collection),
+                std::any::type_name::<T>(),
+            ),
+            Priority::Normal,
+        );
+    }
+
+    /// Wrap a value in a [`DeferDropIn`], which sends it to this garbage can
+    /// when it's dropped.
+    #[inline]
+    pub fn defer<T: Send + 'static>(&self, value: T) -> DeferDropIn<'_, T> {
+        DeferDropIn::new(value, self)
+    }
+
+    /// Get a snapshot of this garbage can's activity.
+    pub fn stats(&self) -> Stats {
+        self.shared.counters.snapshot()
+    }
+
+    /// Get a breakdown of the time this garbage can's background threads
+    /// have spent dropping each type of value, sorted from the most total
+    /// time to the least. This is only available if the garbage can was
+    /// built with [`Builder::profile_types`]; otherwise, it's empty.
+    pub fn type_stats(&self) -> Vec<TypeStats> {
+        match self.profile {
+            Some(ref profile) => profile.snapshot(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Drop values from this garbage can's queue on the current thread, until
+    /// it's empty or `budget` has been used up, and return the number of
+    /// values that were dropped. This only does anything if the garbage can
+    /// was built in [manual mode][Builder::manual]; otherwise, it returns 0.
+    ///
+    /// The budget is checked between drops, so it can be overrun by however
+    /// long the last value takes to drop. At least one value is dropped, if
+    /// there are any, so that a small budget still makes progress.
+    /// [Incrementally dropped][IncrementalDrop] values are dropped a slice at
+    /// a time, and each slice counts as one value.
+    pub fn drain_for(&self, budget: Duration) -> usize {
+        self.drain(Some(Instant::now() + budget), usize::MAX).0
+    }
+
+    /// Drop up to `count` values from this garbage can's queue on the
+    /// current thread, and return the number of values that were dropped.
+    /// This only does anything if the garbage can was built in
+    /// [manual mode][Builder::manual]; otherwise, it returns 0. See
+    /// [`drain_for`][GarbageCan::drain_for] for details.
+    pub fn drain_n(&self, count: usize) -> usize {
+        self.drain(None, count).0
+    }
+
+    /// In manual mode, drop values until the queue is empty, `deadline` has
+    /// passed, or `limit` values have been dropped. Returns the number of
+    /// values that were dropped, and whether the queue was emptied.
+    fn drain(&self, deadline: Option<Instant>, limit: usize) -> (usize, bool) {
+        let drainer = match self.drainer {
+            Some(ref drainer) => drainer,
+            None => return (0, true),
+        };
+
+        // A destructor can't drain the garbage can that's dropping it.
+        if self.is_background_thread() {
+            return (0, false);
+        }
+
+        // While draining, this thread acts like a background thread, so
+        // anything that the destructors throw away is dropped inline.
+        let _draining = Draining::enter(self.id);
+
+        drainer
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .drain(deadline, limit)
+    }
+
+    /// Block until every value that was sent to this garbage can before this
+    /// call has been dropped, including any that this thread had batched up.
+    /// See [`flush`][crate::flush] for details.
+    pub fn flush(&self) {
+        self.flush_until(None);
+    }
+
+    /// Like [`flush`][GarbageCan::flush], but give up after `timeout`.
+    /// Returns `true` if all of the queued values were dropped in time.
+    pub fn flush_timeout(&self, timeout: Duration) -> bool {
+        self.flush_until(Some(Instant::now() + timeout))
+    }
+
+    /// Wait for everything currently in the queues to be dropped. Returns
+    /// false if the deadline passed first.
+    pub(crate) fn flush_until(&self, deadline: Option<Instant>) -> bool {
+        if self.is_background_thread() {
+            return false;
+        }
+
+        self.flush_local();
+
+        // In manual mode, there's no one else to wait for, so do the work
+        // here.
+        if self.drainer.is_some() {
+            return self.drain(deadline, usize::MAX).1;
+        }
+
+        // First, wait for everything that's already queued to be taken off
+        // of the queues. Each queue gets one flush message, sent with high
+        // priority, so that nothing sent after it can hold it up; whoever
+        // takes it catches up with whatever was already waiting in the
+        // other queues.
+        let latch = {
+            let queues = self.shared.queues();
+            let queues = match queues.as_ref() {
+                Some(queues) => queues,
+                None => return true,
+            };
+
+            let latch = Arc::new(Latch::new(queues.len()));
+
+            for senders in queues {
+                let [high, normal, low] = &senders.by_priority;
+                let spill = senders.spill.as_ref().map_or(0, Sender::len);
+
+                let message = Message::Flush(PendingFlush {
+                    latch: latch.clone(),
+                    backlog: [normal.len(), spill, low.len()],
+                });
+
+                let sent = match deadline {
+                    None => high.send(message).is_ok(),
+                    Some(deadline) => high.send_deadline(message, deadline).is_ok(),
+                };
+
+                if !sent {
+                    return false;
+                }
+            }
+
+            latch
+        };
+
+        if !latch.wait(deadline) {
+            return false;
+        }
+
+        // Then, wait for each background thread to finish with whatever it
+        // took. They each count down when they get to their checkpoint, and
+        // carry on, so a slow destructor in one of them doesn't hold up the
+        // others.
+        let latch = {
+            let queues = self.shared.queues();
+
+            if queues.is_none() {
+                return true;
+            }
+
+            let latch = Arc::new(Latch::new(self.controls.len()));
+
+            for control in &self.controls {
+                if control.send(Message::Checkpoint(latch.clone())).is_err() {
+                    latch.arrive();
+                }
+            }
+
+            latch
+        };
+
+        latch.wait(deadline)
+    }
+
+    /// Close the queues and wait for the background threads to drop
+    /// everything in them. After this, garbage is dropped inline.
+    pub(crate) fn shutdown(&self) {
+        self.flush_local();
+
+        // Dropping the senders closes the queues. This waits for any threads
+        // that are currently sending; they hold a read lock.
+        self.shared
+            .queues
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+
+        // A background thread can't wait for itself. In that case the
+        // background threads will finish up on their own once it returns
+        // from this drop.
+        if self.is_background_thread() {
+            return;
+        }
+
+        // In manual mode, finish off everything here, including delayed
+        // values, since there's no one left to wait for them.
+        if let Some(ref drainer) = self.drainer {
+            self.drain(None, usize::MAX);
+
+            let drainer = drainer.lock().unwrap_or_else(PoisonError::into_inner);
+            let _draining = Draining::enter(self.id);
+
+            for garbage in self.timers.take_all() {
+                drainer.worker.destroy(garbage);
+            }
+
+            return;
+        }
+
+        // Keep going until the list is empty, in case any of the background
+        // threads replaced themselves while we were waiting.
+        loop {
+            let handles =
+                mem::take(&mut *self.handles.lock().unwrap_or_else(PoisonError::into_inner));
+
+            if handles.is_empty() {
+                break;
+            }
+
+            for handle in handles {
+                let _ = handle.join();
+            }
+        }
+    }
+}
+
+impl Default for GarbageCan {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DropExecutor for GarbageCan {
+    /// Send the value to the background threads, just like
+    /// [`throw_away`][GarbageCan::throw_away].
+    #[inline]
+    fn execute(&self, garbage: Garbage) {
+        self.throw_away_garbage(garbage, Priority::Normal);
+    }
+}
+
+impl Drop for GarbageCan {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl fmt::Debug for GarbageCan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GarbageCan")
+            .field("overflow", &self.shared.overflow)
+            .field("threads", &self.threads)
+            .field("batch_size", &self.batch_size)
+            .field("manual", &self.drainer.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Everything a background thread needs. This is kept around by the thread
+/// so that it can spawn a replacement for itself after a destructor panics.
+#[derive(Clone)]
+struct Worker {
+    id: usize,
+    name: String,
+    stack_size: Option<usize>,
+    on_panic: PanicPolicy,
+    receivers: [Receiver<Message>; Priority::COUNT],
+    spill: Receiver<Message>,
+    control: Receiver<Message>,
+    slice_size: usize,
+    handles: Arc<Mutex<Vec<JoinHandle<()>>>>,
+    counters: Arc<Counters>,
+    profile: Option<Arc<Profile>>,
+    timers: Arc<Timers>,
+
+    // Flushes that this thread has picked up, but hasn't caught up with yet.
+    // If the thread is replaced, the replacement takes these over.
+    flushes: Arc<Mutex<VecDeque<PendingFlush>>>,
+}
+
+impl Worker {
+    fn spawn(self) -> io::Result<JoinHandle<()>> {
+        let builder = thread::Builder::new().name(self.name.clone());
+
+        let builder = match self.stack_size {
+            Some(size) => builder.stack_size(size),
+            None => builder,
+        };
+
+        builder.spawn(move || self.run())
+    }
+
+    /// Background thread loop: drop values from the queue (and the spill
+    /// queue, if any), as they arrive, until the garbage can is gone.
+    fn run(self) {
+        CURRENT_GARBAGE_CAN.with(|current| current.set(Some(self.id)));
+
+        // Incremental values that are partway through being dropped. Between
+        // each slice, check for a new message, so that nothing gets stuck
+        // behind them.
+        let mut in_progress: VecDeque<Incremental> = VecDeque::new();
+
+        loop {
+            // Don't wait for a message if there's an incremental value to
+            // work on, or past the next timer's deadline.
+            let deadline = match in_progress.is_empty() {
+                true => self.timers.next_deadline(),
+                false => Some(Instant::now()),
+            };
+
+            // Checkpoints come first, then catching up with any flushes that
+            // this thread has picked up, before going back to the usual
+            // order.
+            let mut panicked = false;
+
+            let message = match self.control.try_recv().ok().or_else(|| self.catch_up()) {
+                Some(message) => Some(message),
+                None => {
+                    self.caught_up();
+
+                    match self.recv(deadline) {
+                        Ok(message) => message,
+                        Err(RecvError) => break,
+                    }
+                }
+            };
+
+            if let Some(message) = message {
+                panicked |= self.handle(message, &mut in_progress);
+            }
+
+            panicked |= self.drop_slice(&mut in_progress, self.slice_size);
+
+            for garbage in self.timers.take_expired(Instant::now()) {
+                panicked |= self.destroy(garbage);
+            }
+
+            // A destructor panicked, and the panic policy allowed us to
+            // continue. Don't trust whatever state it left behind in this
+            // thread; start over in a fresh one. If that doesn't work, just
+            // carry on in this one.
+            if panicked && self.respawn(&mut in_progress) {
+                return;
+            }
+        }
+
+        // One of the queues closed, which means the garbage can is gone.
+        // Finish off whatever is left in all of them, including delayed
+        // values, since there's no one left to wait for them.
+        for receiver in self.receivers.iter().chain([&self.spill, &self.control]) {
+            receiver.try_iter().for_each(|message| {
+                self.handle(message, &mut in_progress);
+            });
+        }
+
+        self.finish(&mut in_progress);
+        self.caught_up();
+
+        for garbage in self.timers.take_all() {
+            self.destroy(garbage);
+        }
+    }
+
+    /// Get the next message, from the highest priority queue that has one.
+    /// The spill queue counts as normal priority. Returns `None` if there's
+    /// no message ready, or an error if there's none left and the garbage can
+    /// is gone.
+    fn try_recv(&self) -> Result<Option<Message>, RecvError> {
+        let [high, normal, low] = &self.receivers;
+        let mut disconnected = false;
+
+        for receiver in [high, normal, &self.spill, low] {
+            match receiver.try_recv() {
+                Ok(message) => return Ok(Some(message)),
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => disconnected = true,
+            }
+        }
+
+        match disconnected {
+            true => Err(RecvError),
+            false => Ok(None),
+        }
+    }
+
+    /// Get the next message that was queued before one of the flushes that
+    /// this thread has picked up, from the lower priority queues. Flush
+    /// messages are sent with high priority, so that nothing sent after them
+    /// can hold them up; but then anything sent with a lower priority, or
+    /// spilled over, before the flush might still be queued. Some of it might
+    /// have been taken by other threads already, in which case this takes a
+    /// few later values instead.
+    fn catch_up(&self) -> Option<Message> {
+        let mut flushes = self.lock_flushes();
+        let [_, normal, low] = &self.receivers;
+
+        for (index, receiver) in [normal, &self.spill, low].iter().enumerate() {
+            if flushes.iter().all(|flush| flush.backlog[index] == 0) {
+                continue;
+            }
+
+            let message = receiver.try_recv().ok();
+
+            for flush in flushes.iter_mut() {
+                flush.backlog[index] = match message {
+                    Some(_) => flush.backlog[index].saturating_sub(1),
+                    None => 0,
+                };
+            }
+
+            if message.is_some() {
+                return message;
+            }
+        }
+
+        None
+    }
+
+    /// Arrive at the latches of the flushes that this thread has caught up
+    /// with.
+    fn caught_up(&self) {
+        for flush in mem::take(&mut *self.lock_flushes()) {
+            flush.latch.arrive();
+        }
+    }
+
+    fn lock_flushes(&self) -> MutexGuard<'_, VecDeque<PendingFlush>> {
+        self.flushes.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Like [`try_recv`][Worker::try_recv], but if there's no message ready,
+    /// wait for one until `deadline` (or forever, if it's `None`), and then
+    /// return `None`.
+    fn recv(&self, deadline: Option<Instant>) -> Result<Option<Message>, RecvError> {
+        if let Some(message) = self.try_recv()? {
+            return Ok(Some(message));
+        }
+
+        let [high, normal, low] = &self.receivers;
+
+        // Everything was empty a moment ago, so just take whatever arrives
+        // first.
+        match deadline {
+            None => channel::select! {
+                recv(high) -> message => message.map(Some),
+                recv(normal) -> message => message.map(Some),
+                recv(self.spill) -> message => message.map(Some),
+                recv(low) -> message => message.map(Some),
+                recv(self.control) -> message => message.map(Some),
+            },
+            Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                None => Ok(None),
+                Some(timeout) => channel::select! {
+                    recv(high) -> message => message.map(Some),
+                    recv(normal) -> message => message.map(Some),
+                    recv(self.spill) -> message => message.map(Some),
+                    recv(low) -> message => message.map(Some),
+                    recv(self.control) -> message => message.map(Some),
+                    default(timeout) => Ok(None),
+                },
+            },
+        }
+    }
+
+    /// Handle a message from the main queue. Incremental values are added
+    /// to `in_progress`. Returns true if a destructor panicked.
+    fn handle(&self, message: Message, in_progress: &mut VecDeque<Incremental>) -> bool {
+        match message {
+            Message::Garbage(garbage) => self.destroy(garbage),
+            Message::Batch(batch) => {
+                let mut panicked = false;
+
+                for garbage in batch {
+                    panicked |= self.destroy(garbage);
+                }
+
+                panicked
+            }
+            Message::Incremental(incremental) => {
+                in_progress.push_back(incremental);
+                false
+            }
+            Message::Delayed(deadline, garbage) => {
+                self.timers.insert(deadline, garbage);
+                false
+            }
+            Message::Flush(flush) => {
+                self.lock_flushes().push_back(flush);
+                false
+            }
+            Message::Checkpoint(latch) => {
+                // Incremental values that were taken before the flush have to
+                // be finished off.
+                let panicked = self.finish(in_progress);
+                latch.arrive();
+                panicked
+            }
+        }
+    }
+
+    /// Drop the next slice of the first incremental value in `in_progress`,
+    /// and then move it to the back, if it isn't finished. Returns true if a
+    /// destructor panicked.
+    fn drop_slice(&self, in_progress: &mut VecDeque<Incremental>, len: usize) -> bool {
+        let mut incremental = match in_progress.pop_front() {
+            Some(incremental) => incremental,
+            None => return false,
+        };
+
+        let start = Instant::now();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| incremental.drop_slice(len)));
+        incremental.add_elapsed(start.elapsed());
+
+        match result {
+            Ok(false) => {
+                in_progress.push_back(incremental);
+                false
+            }
+            Ok(true) => {
+                self.record_drop(incremental.type_name(), incremental.elapsed());
+                false
+            }
+            Err(payload) => {
+                self.record_drop(incremental.type_name(), incremental.elapsed());
+                self.on_panic.handle(payload);
+
+                // Give up on dropping it a slice at a time, and just drop the
+                // rest of it.
+                if let Err(payload) =
+                    panic::catch_unwind(AssertUnwindSafe(move || drop(incremental)))
+                {
+                    self.on_panic.handle(payload);
+                }
+
+                true
+            }
+        }
+    }
+
+    /// Finish dropping all of the incremental values in `in_progress`.
+    /// Returns true if a destructor panicked.
+    fn finish(&self, in_progress: &mut VecDeque<Incremental>) -> bool {
+        let mut panicked = false;
+
+        while !in_progress.is_empty() {
+            panicked |= self.drop_slice(in_progress, usize::MAX);
+        }
+
+        panicked
+    }
+
+    /// Drop a value, handling a panic according to the panic policy. Returns
+    /// true if the destructor panicked.
+    fn destroy(&self, garbage: Garbage) -> bool {
+        let type_name = garbage.type_name();
+
+        let start = Instant::now();
+        let result = panic::catch_unwind(AssertUnwindSafe(move || drop(garbage)));
+        self.record_drop(type_name, start.elapsed());
+
+        match result {
+            Ok(()) => false,
+            Err(payload) => {
+                self.on_panic.handle(payload);
+                true
+            }
+        }
+    }
+
+    fn record_drop(&self, type_name: &'static str, elapsed: Duration) {
+        self.counters.record_drop(elapsed);
+
+        if let Some(ref profile) = self.profile {
+            profile.record(type_name, elapsed);
+        }
+    }
+
+    /// Finish dropping the incremental values in `in_progress`, and then
+    /// spawn a replacement for this thread. They're finished first, so that
+    /// the replacement can't reach a flush checkpoint before they're dropped.
+    /// Returns false if the replacement couldn't be spawned.
+    fn respawn(&self, in_progress: &mut VecDeque<Incremental>) -> bool {
+        self.finish(in_progress);
+
+        match self.clone().spawn() {
+            Ok(handle) => {
+                self.handles
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .push(handle);
+
+                true
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+/// Marks the current thread as one of a garbage can's background threads
+/// while it drains the garbage can in manual mode, until it's dropped.
+struct Draining {
+    previous: Option<usize>,
+}
+
+impl Draining {
+    fn enter(id: usize) -> Self {
+        Self {
+            previous: CURRENT_GARBAGE_CAN.with(|current| current.replace(Some(id))),
+        }
+    }
+}
+
+impl Drop for Draining {
+    fn drop(&mut self) {
+        CURRENT_GARBAGE_CAN.with(|current| current.set(self.previous));
+    }
+}
+
+/// The state of a garbage can in manual mode (see [`Builder::manual`]),
+/// which is drained by the application rather than a background thread.
+struct Drainer {
+    // This is never spawned; it's only used for its queues and its
+    // bookkeeping.
+    worker: Worker,
+
+    // Values from a batch that haven't been dropped yet.
+    pending: VecDeque<Garbage>,
+    in_progress: VecDeque<Incremental>,
+}
+
+impl Drainer {
+    /// Drop a value, or a slice of an incremental value. Returns false if
+    /// there was nothing to drop.
+    fn step(&mut self) -> bool {
+        self.pending
+            .extend(self.worker.timers.take_expired(Instant::now()));
+
+        loop {
+            if let Some(garbage) = self.pending.pop_front() {
+                self.worker.destroy(garbage);
+                return true;
+            }
+
+            match self.worker.try_recv() {
+                Ok(Some(Message::Garbage(garbage))) => {
+                    self.worker.destroy(garbage);
+                    return true;
+                }
+                Ok(Some(Message::Batch(batch))) => self.pending.extend(batch),
+
+                // Incremental and delayed values are put aside; there aren't
+                // any flush messages in manual mode.
+                Ok(Some(message)) => {
+                    self.worker.handle(message, &mut self.in_progress);
+
+                    // The delayed value might have expired already.
+                    self.pending
+                        .extend(self.worker.timers.take_expired(Instant::now()));
+                }
+
+                Ok(None) | Err(RecvError) => {
+                    return match self.in_progress.is_empty() {
+                        true => false,
+                        false => {
+                            self.worker
+                                .drop_slice(&mut self.in_progress, self.worker.slice_size);
+                            true
+                        }
+                    };
+                }
+            }
+        }
+    }
+
+    /// Drop values until there are none left, `deadline` has passed, or
+    /// `limit` values have been dropped. Returns the number of values that
+    /// were dropped, and whether there are none left.
+    fn drain(&mut self, deadline: Option<Instant>, limit: usize) -> (usize, bool) {
+        let mut count = 0;
+
+        loop {
+            if count >= limit {
+                return (count, false);
+            }
+
+            if !self.step() {
+                return (count, true);
+            }
+
+            count += 1;
+
+            if matches!(deadline, Some(deadline) if Instant::now() >= deadline) {
+                return (count, false);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossbeam_channel as channel;
+    use std::{
+        any,
+        sync::{Arc, Barrier, Mutex},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use crate::{
+        test_util::{stall, Record, Recorder, ThreadReporter},
+        Builder, DropCost, GarbageCan, Overflow, Priority,
+    };
+
+    struct PanicOnDrop;
+
+    impl Drop for PanicOnDrop {
+        fn drop(&mut self) {
+            panic!("oh no");
+        }
+    }
+
+    /// Create a garbage can with a bounded queue, and stall its background
+    /// thread. The garbage can's queue will be full when this returns; drop
+    /// the returned sender to un-stall it.
+    fn stalled_garbage_can(overflow: Overflow) -> (GarbageCan, channel::Sender<()>) {
+        let can = Builder::new().bounded(1, overflow).build().unwrap();
+        let release = stall(&can);
+        can.throw_away(());
+
+        (can, release)
+    }
+
+    #[test]
+    fn test_overflow_drop_inline() {
+        let (can, _release) = stalled_garbage_can(Overflow::DropInline);
+        let (sender, receiver) = channel::bounded(1);
+
+        can.throw_away(ThreadReporter { chan: sender });
+
+        assert_eq!(
+            receiver.try_recv(),
+            Ok(thread::current().id()),
+            "value wasn't dropped inline when the queue was full"
+        );
+    }
+
+    #[test]
+    fn test_overflow_drop_inline_delayed() {
+        let (can, release) = stalled_garbage_can(Overflow::DropInline);
+        let (sender, receiver) = channel::bounded(1);
+
+        let thrower = thread::scope(|s| {
+            let thrower = s.spawn(|| {
+                can.throw_away_at(ThreadReporter { chan: sender }, Instant::now());
+                thread::current().id()
+            });
+
+            // The delayed value waits for room, rather than being dropped
+            // inline before its deadline
+            thread::sleep(Duration::from_millis(10));
+            assert!(
+                receiver.try_recv().is_err(),
+                "delayed value was dropped inline"
+            );
+
+            drop(release);
+            thrower.join().unwrap()
+        });
+
+        match receiver.recv_timeout(Duration::from_secs(1)) {
+            Ok(id) => assert_ne!(id, thrower),
+            Err(_) => panic!("delayed value wasn't dropped within one second"),
+        }
+    }
+
+    #[test]
+    fn test_overflow_spill() {
+        let (can, release) = stalled_garbage_can(Overflow::Spill);
+        let (sender, receiver) = channel::bounded(1);
+
+        can.throw_away(ThreadReporter { chan: sender });
+        assert!(receiver.try_recv().is_err(), "value was dropped inline");
+
+        drop(release);
+
+        match receiver.recv_timeout(Duration::from_secs(1)) {
+            Ok(id) => assert_ne!(id, thread::current().id()),
+            Err(_) => panic!("spilled value wasn't dropped within one second"),
+        }
+    }
+
+    #[test]
+    fn test_flush() {
+        let (can, release) = stalled_garbage_can(Overflow::Block);
+        let (sender, receiver) = channel::bounded(1);
+
+        assert!(!can.flush_until(Some(Instant::now() + Duration::from_millis(10))));
+
+        drop(release);
+        can.throw_away(ThreadReporter { chan: sender });
+        assert!(can.flush_until(None));
+
+        assert!(
+            receiver.try_recv().is_ok(),
+            "value wasn't dropped before the flush completed"
+        );
+    }
+
+    #[test]
+    fn test_concurrent_flush() {
+        let can = Builder::new().threads(2).build().unwrap();
+        let barrier = Barrier::new(2);
+
+        // Line up the flushes, so that their messages are likely to be
+        // interleaved.
+        thread::scope(|scope| {
+            for _ in 0..2 {
+                scope.spawn(|| {
+                    for _ in 0..1000 {
+                        barrier.wait();
+                        assert!(can.flush_timeout(Duration::from_secs(5)));
+                    }
+                });
+            }
+        });
+
+        let (sender, receiver) = channel::bounded(1);
+        can.throw_away(ThreadReporter { chan: sender });
+        assert!(can.flush_timeout(Duration::from_secs(5)));
+        assert!(receiver.try_recv().is_ok());
+    }
+
+    #[test]
+    fn test_flush_with_stalled_thread() {
+        let can = Builder::new().threads(2).build().unwrap();
+        let release = stall(&can);
+
+        thread::scope(|scope| {
+            let flush = scope.spawn(|| can.flush_timeout(Duration::from_secs(5)));
+            thread::sleep(Duration::from_millis(10));
+
+            // The flush is waiting for the stalled thread, but the other one
+            // keeps going in the meantime
+            for _ in 0..10 {
+                let (sender, receiver) = channel::bounded(1);
+                can.throw_away(ThreadReporter { chan: sender });
+
+                assert!(
+                    receiver.recv_timeout(Duration::from_secs(1)).is_ok(),
+                    "value wasn't dropped while a flush was waiting"
+                );
+            }
+
+            assert!(!flush.is_finished());
+            drop(release);
+            assert!(flush.join().unwrap());
+        });
+    }
+
+    #[test]
+    fn test_flush_with_producer() {
+        struct SlowDrop;
+
+        impl Drop for SlowDrop {
+            fn drop(&mut self) {
+                thread::sleep(Duration::from_micros(500));
+            }
+        }
+
+        let can = GarbageCan::new();
+        let done = Mutex::new(false);
+
+        // Values keep arriving faster than they're dropped, but the flush
+        // only has to wait for the ones that were already queued.
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                while !*done.lock().unwrap() {
+                    can.throw_away(SlowDrop);
+                    thread::sleep(Duration::from_micros(200));
+                }
+            });
+
+            thread::sleep(Duration::from_millis(20));
+
+            let (sender, receiver) = channel::bounded(1);
+            can.throw_away(ThreadReporter { chan: sender });
+
+            let flushed = can.flush_timeout(Duration::from_secs(5));
+            *done.lock().unwrap() = true;
+
+            assert!(flushed, "flush was held up by values sent after it");
+            assert!(receiver.try_recv().is_ok());
+        });
+    }
+
+    #[test]
+    fn test_shutdown() {
+        struct SlowDrop(Arc<Mutex<bool>>);
+
+        impl Drop for SlowDrop {
+            fn drop(&mut self) {
+                thread::sleep(Duration::from_millis(50));
+                *self.0.lock().unwrap() = true;
+            }
+        }
+
+        let can = GarbageCan::new();
+        let dropped = Arc::new(Mutex::new(false));
+
+        can.throw_away(SlowDrop(dropped.clone()));
+        can.shutdown();
+        assert!(*dropped.lock().unwrap(), "shutdown didn't drain the queue");
+
+        let (sender, receiver) = channel::bounded(1);
+        can.throw_away(ThreadReporter { chan: sender });
+        assert_eq!(
+            receiver.try_recv(),
+            Ok(thread::current().id()),
+            "value wasn't dropped inline after shutdown"
+        );
+    }
+
+    #[test]
+    fn test_threads() {
+        let can = Builder::new().threads(2).build().unwrap();
+        let _release = stall(&can);
+
+        let (sender, receiver) = channel::bounded(1);
+        can.throw_away(ThreadReporter { chan: sender });
+
+        match receiver.recv_timeout(Duration::from_secs(1)) {
+            Ok(id) => assert_ne!(id, thread::current().id()),
+            Err(_) => panic!("value was stuck behind a stalled background thread"),
+        }
+    }
+
+    #[test]
+    fn test_ordered() {
+        let can = Builder::new().threads(4).ordered(true).build().unwrap();
+        let record: Record<(usize, usize)> = Default::default();
+
+        thread::scope(|scope| {
+            for sender in 0..4 {
+                let can = &can;
+                let record = &record;
+
+                scope.spawn(move || {
+                    for id in 0..100 {
+                        can.throw_away(Recorder::new((sender, id), record));
+                    }
+                });
+            }
+        });
+
+        assert!(can.flush_until(None));
+
+        let record = record.lock().unwrap();
+        assert_eq!(record.len(), 400);
+
+        for sender in 0..4 {
+            let ids: Vec<usize> = record
+                .iter()
+                .filter(|&&(s, _)| s == sender)
+                .map(|&(_, id)| id)
+                .collect();
+
+            assert_eq!(ids, (0..100).collect::<Vec<usize>>());
+        }
+    }
+
+    #[test]
+    fn test_batching() {
+        let can = Arc::new(Builder::new().batch_size(3).build().unwrap());
+        let (sender, receiver) = channel::unbounded();
+
+        // The batch isn't sent until it's full
+        can.throw_away(ThreadReporter {
+            chan: sender.clone(),
+        });
+        can.throw_away(ThreadReporter {
+            chan: sender.clone(),
+        });
+        assert_eq!(can.stats().enqueued, 0);
+
+        can.throw_away(ThreadReporter {
+            chan: sender.clone(),
+        });
+        assert_eq!(can.stats().enqueued, 3);
+
+        for _ in 0..3 {
+            match receiver.recv_timeout(Duration::from_secs(1)) {
+                Ok(id) => assert_ne!(id, thread::current().id()),
+                Err(_) => panic!("batch wasn't dropped within one second of being sent"),
+            }
+        }
+
+        // Or until it's flushed
+        can.throw_away(ThreadReporter {
+            chan: sender.clone(),
+        });
+        can.flush_local();
+        assert!(receiver.recv_timeout(Duration::from_secs(1)).is_ok());
+
+        // Or until the thread exits
+        let thread = {
+            let can = can.clone();
+
+            thread::spawn(move || {
+                can.throw_away(ThreadReporter { chan: sender });
+                thread::current().id()
+            })
+        };
+
+        let id = thread.join().unwrap();
+        assert_eq!(can.stats().enqueued, 5);
+
+        match receiver.recv_timeout(Duration::from_secs(1)) {
+            Ok(dropper) => assert_ne!(dropper, id),
+            Err(_) => panic!("batch wasn't sent when its thread exited"),
+        }
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_throw_away_parallel() {
+        let can = GarbageCan::new();
+        let (sender, receiver) = channel::unbounded();
+
+        let collection: Vec<ThreadReporter> = (0..1000)
+            .map(|_| ThreadReporter {
+                chan: sender.clone(),
+            })
+            .collect();
+
+        can.throw_away_parallel(collection);
+        assert!(can.flush_until(None));
+
+        let ids: Vec<thread::ThreadId> = receiver.try_iter().collect();
+        assert_eq!(ids.len(), 1000);
+        assert!(ids.iter().all(|&id| id != thread::current().id()));
+    }
+
+    #[test]
+    fn test_incremental() {
+        let can = Builder::new().slice_size(10).build().unwrap();
+        let record: Record<usize> = Default::default();
+
+        // Stall the background thread, so that everything is queued up
+        // before it starts dropping anything
+        let release = stall(&can);
+
+        let big: Vec<Recorder<usize>> = (0..1000).map(|id| Recorder::new(id, &record)).collect();
+
+        can.throw_away_incremental(big);
+        can.throw_away(Recorder::new(1000, &record));
+
+        drop(release);
+        assert!(can.flush_until(None));
+
+        let record = record.lock().unwrap();
+        assert_eq!(record.len(), 1001);
+
+        let position = record.iter().position(|&id| id == 1000).unwrap();
+        assert!(
+            position <= 10,
+            "value was stuck behind an incremental drop (dropped at {})",
+            position
+        );
+        assert_eq!(can.stats().dropped, 3);
+    }
+
+    #[test]
+    fn test_priority() {
+        let can = GarbageCan::new();
+        let record: Record<Priority> = Default::default();
+        let release = stall(&can);
+
+        for &priority in &[Priority::Low, Priority::Normal, Priority::High] {
+            can.throw_away_with_priority(Recorder::new(priority, &record), priority);
+        }
+
+        drop(release);
+        assert!(can.flush_until(None));
+
+        assert_eq!(
+            *record.lock().unwrap(),
+            [Priority::High, Priority::Normal, Priority::Low]
+        );
+    }
+
+    #[test]
+    fn test_delayed() {
+        let can = GarbageCan::new();
+        let (sender, receiver) = channel::unbounded();
+        let start = Instant::now();
+
+        can.throw_away_after(
+            ThreadReporter {
+                chan: sender.clone(),
+            },
+            Duration::from_millis(100),
+        );
+        can.throw_away_after(ThreadReporter { chan: sender }, Duration::from_secs(3600));
+
+        // Delayed values aren't flushed
+        assert!(can.flush_until(None));
+        assert!(receiver.try_recv().is_err(), "value was dropped early");
+
+        match receiver.recv_timeout(Duration::from_secs(1)) {
+            Ok(id) => assert_ne!(id, thread::current().id()),
+            Err(_) => panic!("delayed value wasn't dropped"),
+        }
+        assert!(start.elapsed() >= Duration::from_millis(100));
+
+        // Whatever is left is dropped when the garbage can is shut down
+        can.shutdown();
+        assert!(
+            receiver.try_recv().is_ok(),
+            "shutdown didn't drop delayed values"
+        );
+    }
+
+    #[test]
+    fn test_defer() {
+        struct NameReporter {
+            chan: channel::Sender<Option<String>>,
+        }
+
+        impl Drop for NameReporter {
+            fn drop(&mut self) {
+                let name = thread::current().name().map(str::to_owned);
+                self.chan.send(name).unwrap();
+            }
+        }
+
+        let can = Builder::new().name("custom").build().unwrap();
+        let (sender, receiver) = channel::bounded(1);
+
+        drop(can.defer(NameReporter { chan: sender }));
+
+        match receiver.recv_timeout(Duration::from_secs(1)) {
+            Ok(name) => assert_eq!(name.as_deref(), Some("custom")),
+            Err(_) => panic!("value wasn't dropped within one second of being dropped"),
+        }
+    }
+
+    #[test]
+    fn test_panic_hook() {
+        let (payload_send, payloads) = channel::unbounded();
+
+        let can = Builder::new()
+            .panic_hook(move |payload| {
+                let message = payload.downcast_ref::<&str>().copied().unwrap_or_default();
+                payload_send.send(message).unwrap();
+            })
+            .build()
+            .unwrap();
+
+        let (sender, receiver) = channel::unbounded();
+
+        can.throw_away(ThreadReporter {
+            chan: sender.clone(),
+        });
+        can.throw_away(PanicOnDrop);
+        can.throw_away(ThreadReporter { chan: sender });
+        can.flush();
+
+        assert_eq!(payloads.try_recv(), Ok("oh no"));
+
+        let before = receiver.try_recv().unwrap();
+        let after = receiver.try_recv().unwrap();
+        assert_ne!(before, after, "background thread wasn't replaced");
+        assert_ne!(after, thread::current().id());
+    }
+
+    #[test]
+    fn test_panicking_hook() {
+        let can = Builder::new()
+            .threads(2)
+            .panic_hook(|_| panic!("panic hook panicked"))
+            .build()
+            .unwrap();
+
+        can.throw_away(PanicOnDrop);
+        assert!(
+            can.flush_timeout(Duration::from_secs(5)),
+            "flush didn't complete after the panic hook panicked"
+        );
+
+        let (sender, receiver) = channel::bounded(1);
+        can.throw_away(ThreadReporter { chan: sender });
+
+        match receiver.recv_timeout(Duration::from_secs(1)) {
+            Ok(id) => assert_ne!(id, thread::current().id()),
+            Err(_) => panic!("value wasn't dropped after the panic hook panicked"),
+        }
+    }
+
+    #[test]
+    fn test_unavailable() {
+        let can = GarbageCan::unavailable();
+        let (sender, receiver) = channel::bounded(1);
+
+        can.throw_away(ThreadReporter { chan: sender });
+        assert_eq!(receiver.try_recv(), Ok(thread::current().id()));
+        assert!(can.flush_timeout(Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn test_stats() {
+        let (can, release) = stalled_garbage_can(Overflow::DropInline);
+        can.throw_away(());
+
+        let stats = can.stats();
+        assert_eq!(stats.enqueued, 2);
+        assert_eq!(stats.dropped, 0);
+        assert_eq!(stats.queue_len, 2);
+        assert_eq!(stats.inline_drops, 1);
+
+        thread::sleep(Duration::from_millis(10));
+        drop(release);
+        can.flush();
+
+        let stats = can.stats();
+        assert_eq!(stats.enqueued, 2);
+        assert_eq!(stats.dropped, 2);
+        assert_eq!(stats.queue_len, 0);
+        assert_eq!(stats.max_queue_len, 2);
+        assert!(stats.max_drop_time >= Duration::from_millis(10));
+        assert!(stats.drop_time >= stats.max_drop_time);
+    }
+
+    #[test]
+    fn test_type_stats() {
+        let can = Builder::new().profile_types(true).build().unwrap();
+
+        can.throw_away(vec![1, 2, 3]);
+        can.throw_away(vec![4, 5, 6]);
+        can.throw_away(String::from("hello"));
+        can.flush();
+
+        let mut stats: Vec<(&str, u64)> = can
+            .type_stats()
+            .iter()
+            .map(|stats| (stats.type_name, stats.dropped))
+            .collect();
+
+        stats.sort();
+
+        assert_eq!(
+            stats,
+            [
+                (any::type_name::<String>(), 1),
+                (any::type_name::<Vec<i32>>(), 2)
+            ]
+        );
+
+        assert!(GarbageCan::new().type_stats().is_empty());
+    }
+
+    #[test]
+    fn test_throw_away_if_costly() {
+        #[allow(dead_code)]
+        struct Costly(usize, ThreadReporter);
+
+        impl DropCost for Costly {
+            fn estimated_cost(&self) -> usize {
+                self.0
+            }
+        }
+
+        let can = Builder::new().cost_threshold(10).build().unwrap();
+        let (sender, receiver) = channel::unbounded();
+
+        can.throw_away_if_costly(Costly(
+            5,
+            ThreadReporter {
+                chan: sender.clone(),
+            },
+        ));
+        assert_eq!(receiver.try_recv(), Ok(thread::current().id()));
+
+        can.throw_away_if_costly(Costly(20, ThreadReporter { chan: sender }));
+        can.flush();
+        assert_ne!(receiver.try_recv().unwrap(), thread::current().id());
+    }
+
+    #[test]
+    fn test_manual() {
+        let can = Builder::new().manual(true).batch_size(2).build().unwrap();
+        let (sender, receiver) = channel::unbounded();
+
+        for _ in 0..3 {
+            can.throw_away(ThreadReporter {
+                chan: sender.clone(),
+            });
+        }
+
+        can.throw_away_with_priority(
+            ThreadReporter {
+                chan: sender.clone(),
+            },
+            Priority::High,
+        );
+
+        can.throw_away_incremental(vec![(); 3000]);
+        can.throw_away_at(ThreadReporter { chan: sender }, Instant::now());
+
+        // Nothing happens on its own
+        thread::sleep(Duration::from_millis(10));
+        assert!(receiver.try_recv().is_err());
+
+        // The high priority value comes first, then the first batch
+        assert_eq!(can.drain_n(2), 2);
+        assert_eq!(receiver.try_iter().count(), 2);
+
+        // Then the rest of the batches (the partial one was sent along with
+        // the incremental value), the incremental value in three slices, and
+        // the delayed value
+        assert_eq!(can.drain_for(Duration::from_secs(60)), 6);
+        assert_eq!(can.drain_n(1), 0);
+
+        let ids: Vec<_> = receiver.try_iter().collect();
+        assert_eq!(ids, [thread::current().id(); 3]);
+    }
+
+    #[test]
+    fn test_manual_delayed() {
+        let can = Builder::new().manual(true).build().unwrap();
+        let value = Arc::new(());
+
+        // An expired delayed value is dropped, even when it's the last thing
+        // in the queue
+        can.throw_away_at(value.clone(), Instant::now());
+        assert_eq!(can.drain_n(1), 1);
+        assert_eq!(Arc::strong_count(&value), 1);
+
+        can.throw_away_at(value.clone(), Instant::now());
+        can.flush();
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn test_manual_bounded_block() {
+        let can = Builder::new()
+            .manual(true)
+            .bounded(2, Overflow::Block)
+            .build()
+            .unwrap();
+
+        let value = Arc::new(());
+
+        for _ in 0..3 {
+            can.throw_away(value.clone());
+        }
+
+        // Nothing would ever make room for the third value, so it was dropped
+        // inline instead of blocking
+        assert_eq!(Arc::strong_count(&value), 3);
+
+        // Delayed values don't block or get dropped early either
+        can.throw_away_at(value.clone(), Instant::now());
+        assert_eq!(Arc::strong_count(&value), 4);
+
+        assert_eq!(can.drain_for(Duration::from_secs(60)), 3);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+}
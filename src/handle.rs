@@ -0,0 +1,209 @@
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError},
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
+};
+
+#[cfg(doc)]
+use crate::DeferDrop;
+
+#[derive(Default)]
+struct State {
+    dropped: bool,
+    waker: Option<Waker>,
+}
+
+#[derive(Default)]
+struct Signal {
+    state: Mutex<State>,
+    condvar: Condvar,
+}
+
+impl Signal {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Handle that reports when a particular deferred value has been dropped.
+/// Create one with [`DeferDrop::drop_with_handle`] or
+/// [`DeferDropIn::drop_with_handle`][crate::DeferDropIn::drop_with_handle].
+///
+/// The handle can be polled with [`is_dropped`][DropHandle::is_dropped],
+/// waited on with [`wait`][DropHandle::wait], or `await`ed, since it
+/// implements [`Future`]. It completes once the value's destructor has
+/// finished running (even if it panicked).
+///
+/// If the value is never dropped (for instance, because the program exits
+/// while it's still queued), the handle never completes.
+///
+/// # Example
+///
+/// ```
+/// use defer_drop::DeferDrop;
+///
+/// let value = DeferDrop::new(vec![1, 2, 3]);
+/// let handle = DeferDrop::drop_with_handle(value);
+///
+/// handle.wait();
+/// ```
+#[must_use = "a DropHandle does nothing unless you wait for it"]
+pub struct DropHandle {
+    signal: Arc<Signal>,
+}
+
+impl DropHandle {
+    /// Check if the value has been dropped yet, without blocking.
+    pub fn is_dropped(&self) -> bool {
+        self.signal.lock().dropped
+    }
+
+    /// Block until the value has been dropped.
+    pub fn wait(&self) {
+        let mut state = self.signal.lock();
+
+        while !state.dropped {
+            state = self
+                .signal
+                .condvar
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    /// Block until the value has been dropped, or until `timeout` has passed.
+    /// Returns `true` if the value was dropped.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.signal.lock();
+
+        while !state.dropped {
+            let timeout = match deadline.checked_duration_since(Instant::now()) {
+                Some(timeout) => timeout,
+                None => return false,
+            };
+
+            state = self
+                .signal
+                .condvar
+                .wait_timeout(state, timeout)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+
+        true
+    }
+}
+
+impl Future for DropHandle {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut state = self.signal.lock();
+
+        match state.dropped {
+            true => Poll::Ready(()),
+            false => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl fmt::Debug for DropHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DropHandle")
+            .field("dropped", &self.is_dropped())
+            .finish()
+    }
+}
+
+/// The other side of a [`DropHandle`]; completes the handle when it's
+/// dropped.
+struct Notifier {
+    signal: Arc<Signal>,
+}
+
+impl Drop for Notifier {
+    fn drop(&mut self) {
+        let waker = {
+            let mut state = self.signal.lock();
+            state.dropped = true;
+            state.waker.take()
+        };
+
+        self.signal.condvar.notify_all();
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// A value bundled with a [`Notifier`]. Struct fields are dropped in order,
+/// so the handle completes after the value is dropped, even if its
+/// destructor panics.
+pub(crate) struct WithHandle<T> {
+    _value: T,
+    _notifier: Notifier,
+}
+
+/// Bundle a value with a notifier, returning the bundle and the handle that
+/// it completes.
+pub(crate) fn with_handle<T>(value: T) -> (WithHandle<T>, DropHandle) {
+    let signal: Arc<Signal> = Default::default();
+
+    let bundle = WithHandle {
+        _value: value,
+        _notifier: Notifier {
+            signal: signal.clone(),
+        },
+    };
+
+    (bundle, DropHandle { signal })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        future::Future,
+        pin::Pin,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        task::{Context, Poll, Wake, Waker},
+    };
+
+    use super::with_handle;
+
+    struct FlagWaker(AtomicBool);
+
+    impl Wake for FlagWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_future() {
+        let flag = Arc::new(FlagWaker(AtomicBool::new(false)));
+        let waker = Waker::from(flag.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        let (bundle, mut handle) = with_handle(vec![1, 2, 3]);
+
+        assert_eq!(Pin::new(&mut handle).poll(&mut cx), Poll::Pending);
+        assert!(!handle.is_dropped());
+
+        drop(bundle);
+
+        assert!(flag.0.load(Ordering::SeqCst), "handle wasn't woken");
+        assert!(handle.is_dropped());
+        assert_eq!(Pin::new(&mut handle).poll(&mut cx), Poll::Ready(()));
+    }
+}
@@ -0,0 +1,280 @@
+use std::{
+    any,
+    collections::{btree_map, hash_map, vec_deque, BTreeMap, HashMap, VecDeque},
+    mem::{self, ManuallyDrop},
+    ops::{Deref, DerefMut},
+    time::Duration,
+    vec,
+};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::global_garbage_can;
+
+#[cfg(doc)]
+use crate::{Builder, DeferDrop, GarbageCan};
+
+/// A value that can be dropped a piece at a time.
+///
+/// Dropping a collection with millions of elements can keep a background
+/// thread busy for a long time, and everything queued behind it has to wait.
+/// A value that implements `IncrementalDrop` can be broken up into
+/// [`Pieces`][IncrementalDrop::Pieces], which the background thread drops a
+/// slice at a time (see [`Builder::slice_size`]), checking for other garbage
+/// between slices. Several incremental values are dropped round-robin, so
+/// each of them makes progress.
+///
+/// Use [`IncrementalDeferDrop`] or [`GarbageCan::throw_away_incremental`] to
+/// drop a value incrementally.
+pub trait IncrementalDrop: Send + 'static {
+    /// An iterator over the pieces of the value. Each piece is dropped as
+    /// it's taken from the iterator, and then the iterator itself is
+    /// dropped, which should free whatever is left.
+    type Pieces: Iterator + Send + 'static;
+
+    /// Break the value up into pieces.
+    fn into_pieces(self) -> Self::Pieces;
+}
+
+impl<T: Send + 'static> IncrementalDrop for Vec<T> {
+    type Pieces = vec::IntoIter<T>;
+
+    #[inline]
+    fn into_pieces(self) -> Self::Pieces {
+        self.into_iter()
+    }
+}
+
+impl<T: Send + 'static> IncrementalDrop for VecDeque<T> {
+    type Pieces = vec_deque::IntoIter<T>;
+
+    #[inline]
+    fn into_pieces(self) -> Self::Pieces {
+        self.into_iter()
+    }
+}
+
+impl<K, V, S> IncrementalDrop for HashMap<K, V, S>
+where
+    K: Send + 'static,
+    V: Send + 'static,
+    S: Send + 'static,
+{
+    type Pieces = hash_map::IntoIter<K, V>;
+
+    #[inline]
+    fn into_pieces(self) -> Self::Pieces {
+        self.into_iter()
+    }
+}
+
+impl<K: Send + 'static, V: Send + 'static> IncrementalDrop for BTreeMap<K, V> {
+    type Pieces = btree_map::IntoIter<K, V>;
+
+    #[inline]
+    fn into_pieces(self) -> Self::Pieces {
+        self.into_iter()
+    }
+}
+
+/// Type-erased pieces of an [`IncrementalDrop`] value.
+trait Slices: Send {
+    /// Drop up to `len` pieces. Returns true if there are none left.
+    fn drop_slice(&mut self, len: usize) -> bool;
+}
+
+impl<I: Iterator + Send> Slices for I {
+    fn drop_slice(&mut self, len: usize) -> bool {
+        self.by_ref().take(len).count() < len
+    }
+}
+
+/// A type-erased [`IncrementalDrop`] value that's in the process of being
+/// dropped.
+pub(crate) struct Incremental {
+    // This is `None` once all of the pieces have been dropped.
+    pieces: Option<Box<dyn Slices>>,
+    type_name: &'static str,
+    elapsed: Duration,
+}
+
+impl Incremental {
+    pub fn new<T: IncrementalDrop>(value: T) -> Self {
+        Self {
+            pieces: Some(Box::new(value.into_pieces())),
+            type_name: any::type_name::<T>(),
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    /// Drop up to `len` pieces. Returns true once everything, including the
+    /// iterator, has been dropped.
+    pub fn drop_slice(&mut self, len: usize) -> bool {
+        match self.pieces.as_mut() {
+            None => true,
+            Some(pieces) => match pieces.drop_slice(len) {
+                false => false,
+                true => {
+                    self.pieces = None;
+                    true
+                }
+            },
+        }
+    }
+
+    /// The name of the type of the value, as reported by
+    /// [`std::any::type_name`].
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+
+    /// The total time spent dropping slices of the value so far.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    pub fn add_elapsed(&mut self, elapsed: Duration) {
+        self.elapsed += elapsed;
+    }
+}
+
+/// Wrapper type that, when dropped, sends the inner value to the global
+/// background thread to be dropped [incrementally][IncrementalDrop].
+///
+/// A [`DeferDrop`] holding a huge collection stalls the background thread
+/// until the whole thing has been dropped. An `IncrementalDeferDrop` is
+/// dropped a slice at a time instead, so other garbage can be dropped in
+/// between.
+///
+/// # Example
+///
+/// ```
+/// use defer_drop::IncrementalDeferDrop;
+///
+/// let value = IncrementalDeferDrop::new(vec![String::from("Hello"); 100_000]);
+/// drop(value);
+///
+/// defer_drop::flush();
+/// ```
+#[repr(transparent)]
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct IncrementalDeferDrop<T: IncrementalDrop> {
+    inner: ManuallyDrop<T>,
+}
+
+impl<T: IncrementalDrop> IncrementalDeferDrop<T> {
+    /// Create a new `IncrementalDeferDrop` value.
+    #[inline]
+    pub fn new(value: T) -> Self {
+        IncrementalDeferDrop {
+            inner: ManuallyDrop::new(value),
+        }
+    }
+
+    /// Unwrap the `IncrementalDeferDrop`, returning the inner value. This has
+    /// the effect of cancelling the deferred drop behavior; ownership of the
+    /// inner value is transferred to the caller.
+    pub fn into_inner(mut this: Self) -> T {
+        let value = unsafe { ManuallyDrop::take(&mut this.inner) };
+        mem::forget(this);
+        value
+    }
+}
+
+impl<T: IncrementalDrop> Drop for IncrementalDeferDrop<T> {
+    fn drop(&mut self) {
+        global_garbage_can().throw_away_incremental(unsafe { ManuallyDrop::take(&mut self.inner) });
+    }
+}
+
+impl<T: IncrementalDrop> From<T> for IncrementalDeferDrop<T> {
+    #[inline]
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<T: IncrementalDrop> AsRef<T> for IncrementalDeferDrop<T> {
+    #[inline]
+    fn as_ref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T: IncrementalDrop> AsMut<T> for IncrementalDeferDrop<T> {
+    #[inline]
+    fn as_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+impl<T: IncrementalDrop> Deref for IncrementalDeferDrop<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl<T: IncrementalDrop> DerefMut for IncrementalDeferDrop<T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T: IncrementalDrop + Serialize> Serialize for IncrementalDeferDrop<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.as_ref().serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T: IncrementalDrop + Deserialize<'de>> Deserialize<'de> for IncrementalDeferDrop<T> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        T::deserialize(deserializer).map(Self::new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use super::Incremental;
+
+    struct DropCounter(Arc<AtomicUsize>);
+
+    impl Drop for DropCounter {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_drop_slice() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let value: Vec<DropCounter> = (0..25).map(|_| DropCounter(count.clone())).collect();
+        let mut incremental = Incremental::new(value);
+
+        assert!(!incremental.drop_slice(10));
+        assert_eq!(count.load(Ordering::SeqCst), 10);
+
+        assert!(!incremental.drop_slice(10));
+        assert_eq!(count.load(Ordering::SeqCst), 20);
+
+        assert!(incremental.drop_slice(10));
+        assert_eq!(count.load(Ordering::SeqCst), 25);
+    }
+}
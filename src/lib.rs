@@ -1,6 +1,9 @@
 /*!
 A utility type that allows you to defer dropping your data to a background
-thread. See [`DeferDrop`] for details.
+thread. See [`DeferDrop`] for details, [`GarbageCan`] for creating your own
+background threads, and [`DropExecutor`] for sending deferred values
+somewhere else entirely. Values that aren't [`Send`] can be deferred with the
+[`local`] module instead.
 
 Inspired by [https://abramov.io/rust-dropping-things-in-another-thread](https://abramov.io/rust-dropping-things-in-another-thread)
 
@@ -8,17 +11,99 @@ Inspired by [https://abramov.io/rust-dropping-things-in-another-thread](https://
 
 - `serde`: when enabled, adds a [`Serialize`] and [`Deserialize`]
   implementation to [`DeferDrop`]
+- `rayon`: when enabled, adds [`ParallelDeferDrop`], which drops the
+  elements of a large collection in parallel on the rayon thread pool
+- `tokio`: when enabled, adds the [`tokio`] module, which defers
+  drops to the tokio blocking thread pool instead of a dedicated background
+  thread
+- `test-executor`: when enabled, adds [`TestExecutor`], which captures
+  deferred values in tests, so that they can be dropped at a known point.
+  Enable it in your `[dev-dependencies]`, since it adds a check to every
+  deferred drop
 */
+#![doc = ""]
+#![cfg_attr(feature = "rayon", doc = "[`ParallelDeferDrop`]: ParallelDeferDrop")]
+#![cfg_attr(
+    not(feature = "rayon"),
+    doc = "[`ParallelDeferDrop`]: https://docs.rs/defer-drop/latest/defer_drop/struct.ParallelDeferDrop.html"
+)]
+#![cfg_attr(feature = "tokio", doc = "[`tokio`]: mod@tokio")]
+#![cfg_attr(
+    not(feature = "tokio"),
+    doc = "[`tokio`]: https://docs.rs/defer-drop/latest/defer_drop/tokio/index.html"
+)]
+#![cfg_attr(feature = "test-executor", doc = "[`TestExecutor`]: TestExecutor")]
+#![cfg_attr(
+    not(feature = "test-executor"),
+    doc = "[`TestExecutor`]: https://docs.rs/defer-drop/latest/defer_drop/struct.TestExecutor.html"
+)]
+
+mod adaptive;
+mod arc;
+mod executor;
+mod garbage;
+mod garbage_can;
+mod handle;
+mod incremental;
+mod scope;
+mod stats;
+mod timers;
+
+#[cfg(test)]
+mod test_util;
+
+pub mod local;
+pub mod priority;
+
+#[cfg(feature = "rayon")]
+mod parallel;
+
+#[cfg(feature = "tokio")]
+pub mod tokio;
+
+#[cfg(feature = "test-executor")]
+mod testing;
 
 use std::{
+    any::{self, Any},
+    cmp::Ordering,
+    error::Error,
+    fmt,
+    hash::{Hash, Hasher},
+    io,
+    marker::PhantomData,
     mem::{self, ManuallyDrop},
     ops::{Deref, DerefMut},
-    thread::{self, JoinHandle},
+    panic::{self, AssertUnwindSafe},
+    process,
+    sync::{
+        atomic::{AtomicUsize, Ordering as AtomicOrdering},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
 
-use crossbeam_channel::{self as channel, Sender};
 use once_cell::sync::OnceCell;
 
+use priority::{Level, Normal};
+
+pub use adaptive::{AdaptiveDeferDrop, DropCost};
+pub use arc::DeferDropArc;
+pub use executor::{CurrentThread, DropExecutor};
+pub use garbage::Garbage;
+pub use garbage_can::GarbageCan;
+pub use handle::DropHandle;
+pub use incremental::{IncrementalDeferDrop, IncrementalDrop};
+pub use priority::Priority;
+pub use scope::{scope, Scope, ScopedDeferDrop};
+pub use stats::{Stats, TypeStats};
+
+#[cfg(feature = "test-executor")]
+pub use testing::{TestExecutor, TestGuard};
+
+#[cfg(feature = "rayon")]
+pub use parallel::ParallelDeferDrop;
+
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
@@ -38,23 +123,30 @@ use serde::{Deserialize, Serialize};
 /// thread comes with its own costs, so it should only be done if performance
 /// profiling indicates that it's a performance gain.
 ///
-/// There is only one global worker thread. Dropped values are enqueued in an
-/// unbounded channel to be consumed by this thread; if you produce more
-/// garbage than the thread can handle, this will cause unbounded memory
-/// consumption. There is currently no way for the thread to signal or block
-/// if it is overwhelmed.
+/// By default, there is only one global worker thread. Dropped values are
+/// enqueued in an unbounded channel to be consumed by this thread; if you
+/// produce more garbage than the thread can handle, this will cause unbounded
+/// memory consumption. Use a [`Builder`] to bound the channel and choose what
+/// happens when it's full (see [`Overflow`]), or to add more worker threads.
 ///
-/// All of the standard non-determinism threading caveats apply here. The
-/// objects are guaranteed to be destructed in the order received through a
-/// channel, which means that objects sent from a single thread will be
-/// destructed in order. However, there is no guarantee about the ordering of
+/// All of the standard non-determinism threading caveats apply here. With a
+/// single worker thread (or in [ordered][Builder::ordered] mode), the objects
+/// are guaranteed to be destructed in the order received through a channel,
+/// which means that objects sent from a single thread will be destructed in
+/// order. However, there is no guarantee about the ordering of
 /// interleaved values from different threads. Additionally, there are no
 /// guarantees about how long the values will be queued before being dropped,
 /// or even that they will be dropped at all. If your `main` thread terminates
 /// before all drops could be completed, they will be silently lost (as though
-/// via a [`mem::forget`]). This behavior is entirely up to your OS's thread
-/// scheduler. There is no way to receive a signal indicating when a particular
-/// object was dropped.
+/// via a [`mem::forget`]), unless you're holding a [`DropGuard`]. This
+/// behavior is entirely up to your OS's thread scheduler. Use
+/// [`DeferDrop::drop_with_handle`] to find out when a particular object was
+/// dropped, or [`flush`] to wait until everything dropped so far has been
+/// destroyed.
+///
+/// The second type parameter is the [priority] of the drop; by default, it's
+/// [`Normal`]. Use [`DeferDrop::with_priority`] to create a `DeferDrop` with a
+/// different priority.
 ///
 /// # Example
 ///
@@ -82,16 +174,27 @@ use serde::{Deserialize, Serialize};
 /// ```
 #[repr(transparent)]
 #[derive(Debug, Default, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
-pub struct DeferDrop<T: Send + 'static> {
+pub struct DeferDrop<T: Send + 'static, P: Level = Normal> {
     inner: ManuallyDrop<T>,
+    priority: PhantomData<P>,
 }
 
 impl<T: Send + 'static> DeferDrop<T> {
     /// Create a new `DeferDrop` value.
     #[inline]
     pub fn new(value: T) -> Self {
+        Self::with_priority(value)
+    }
+}
+
+impl<T: Send + 'static, P: Level> DeferDrop<T, P> {
+    /// Create a new `DeferDrop` value, with the priority `P`. See the
+    /// [`priority`] module for details.
+    #[inline]
+    pub fn with_priority(value: T) -> Self {
         DeferDrop {
             inner: ManuallyDrop::new(value),
+            priority: PhantomData,
         }
     }
 
@@ -103,18 +206,70 @@ impl<T: Send + 'static> DeferDrop<T> {
         mem::forget(this);
         value
     }
+
+    /// Drop the `DeferDrop`, returning a [`DropHandle`] that can be used to
+    /// find out when the inner value has actually been dropped by the
+    /// background thread.
+    pub fn drop_with_handle(this: Self) -> DropHandle {
+        let (value, handle) = handle::with_handle(Self::into_inner(this));
+        let can = global_garbage_can();
+
+        // Don't leave the value sitting in this thread's batch, where the
+        // handle might never complete.
+        can.throw_away_garbage(
+            Garbage::with_type_name(value, any::type_name::<T>()),
+            P::PRIORITY,
+        );
+        can.flush_local();
+        handle
+    }
+
+    /// Drop the `DeferDrop`, but keep the inner value alive in the background
+    /// thread until `delay` has passed. See
+    /// [`GarbageCan::throw_away_at`] for details.
+    ///
+    /// ```
+    /// use defer_drop::DeferDrop;
+    /// use std::time::Duration;
+    ///
+    /// let old_config = DeferDrop::new(vec![1, 2, 3]);
+    ///
+    /// // Give any in-flight readers a few seconds to finish with it
+    /// DeferDrop::drop_after(old_config, Duration::from_secs(5));
+    /// ```
+    #[inline]
+    pub fn drop_after(this: Self, delay: Duration) {
+        global_garbage_can().throw_away_after(Self::into_inner(this), delay);
+    }
+
+    /// Drop the `DeferDrop`, but keep the inner value alive in the background
+    /// thread until `deadline` has passed. See
+    /// [`GarbageCan::throw_away_at`] for details.
+    #[inline]
+    pub fn drop_at(this: Self, deadline: Instant) {
+        global_garbage_can().throw_away_at(Self::into_inner(this), deadline);
+    }
 }
 
 static GARBAGE_CAN: OnceCell<GarbageCan> = OnceCell::new();
 
-impl<T: Send + 'static> Drop for DeferDrop<T> {
+impl<T: Send + 'static, P: Level> Drop for DeferDrop<T, P> {
     fn drop(&mut self) {
-        GARBAGE_CAN
-            .get_or_init(|| GarbageCan::new("defer-drop background thread".to_owned()))
-            .throw_away(unsafe { ManuallyDrop::take(&mut self.inner) });
+        global_garbage_can()
+            .throw_away_with_priority(unsafe { ManuallyDrop::take(&mut self.inner) }, P::PRIORITY);
     }
 }
 
+fn global_garbage_can() -> &'static GarbageCan {
+    // If the background thread can't be spawned, fall back to dropping
+    // everything inline, rather than panicking in a destructor.
+    GARBAGE_CAN.get_or_init(|| {
+        Builder::new()
+            .build()
+            .unwrap_or_else(|_| GarbageCan::unavailable())
+    })
+}
+
 impl<T: Send + 'static> From<T> for DeferDrop<T> {
     #[inline]
     fn from(value: T) -> Self {
@@ -122,21 +277,21 @@ impl<T: Send + 'static> From<T> for DeferDrop<T> {
     }
 }
 
-impl<T: Send + 'static> AsRef<T> for DeferDrop<T> {
+impl<T: Send + 'static, P: Level> AsRef<T> for DeferDrop<T, P> {
     #[inline]
     fn as_ref(&self) -> &T {
         &self.inner
     }
 }
 
-impl<T: Send + 'static> AsMut<T> for DeferDrop<T> {
+impl<T: Send + 'static, P: Level> AsMut<T> for DeferDrop<T, P> {
     #[inline]
     fn as_mut(&mut self) -> &mut T {
         &mut self.inner
     }
 }
 
-impl<T: Send + 'static> Deref for DeferDrop<T> {
+impl<T: Send + 'static, P: Level> Deref for DeferDrop<T, P> {
     type Target = T;
 
     #[inline]
@@ -145,7 +300,7 @@ impl<T: Send + 'static> Deref for DeferDrop<T> {
     }
 }
 
-impl<T: Send + 'static> DerefMut for DeferDrop<T> {
+impl<T: Send + 'static, P: Level> DerefMut for DeferDrop<T, P> {
     #[inline]
     fn deref_mut(&mut self) -> &mut Self::Target {
         &mut self.inner
@@ -153,7 +308,7 @@ impl<T: Send + 'static> DerefMut for DeferDrop<T> {
 }
 
 #[cfg(feature = "serde")]
-impl<T: Serialize + Send + 'static> Serialize for DeferDrop<T> {
+impl<T: Serialize + Send + 'static, P: Level> Serialize for DeferDrop<T, P> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: serde::Serializer,
@@ -163,69 +318,771 @@ impl<T: Serialize + Send + 'static> Serialize for DeferDrop<T> {
 }
 
 #[cfg(feature = "serde")]
-impl<'de, T: Deserialize<'de> + Send + 'static> Deserialize<'de> for DeferDrop<T> {
+impl<'de, T: Deserialize<'de> + Send + 'static, P: Level> Deserialize<'de> for DeferDrop<T, P> {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: serde::Deserializer<'de>,
     {
-        T::deserialize(deserializer).map(Self::new)
+        T::deserialize(deserializer).map(Self::with_priority)
+    }
+}
+
+/// Wrapper type that, when dropped, sends the inner value to a particular
+/// [`GarbageCan`] to be dropped. Create one with [`GarbageCan::defer`] or
+/// [`DeferDropIn::new`].
+///
+/// This works just like [`DeferDrop`], except that instead of the global
+/// garbage can, it uses the one it was created with. This way, a subsystem can
+/// have its own background thread, which doesn't compete with garbage from
+/// the rest of a program.
+///
+/// More generally, the value can be sent to any [`DropExecutor`], which is
+/// the third type parameter. By default, that's a [`GarbageCan`].
+pub struct DeferDropIn<'a, T: Send + 'static, E: DropExecutor + ?Sized = GarbageCan> {
+    inner: ManuallyDrop<T>,
+    executor: &'a E,
+}
+
+impl<'a, T: Send + 'static, E: DropExecutor + ?Sized> DeferDropIn<'a, T, E> {
+    /// Create a new `DeferDropIn` value, which will send `value` to
+    /// `executor` when it's dropped.
+    #[inline]
+    pub fn new(value: T, executor: &'a E) -> Self {
+        DeferDropIn {
+            inner: ManuallyDrop::new(value),
+            executor,
+        }
+    }
+
+    /// Get the [`DropExecutor`] that this value will be sent to.
+    #[inline]
+    pub fn executor(this: &Self) -> &'a E {
+        this.executor
+    }
+
+    /// Unwrap the `DeferDropIn`, returning the inner value. This has the
+    /// effect of cancelling the deferred drop behavior; ownership of the
+    /// inner value is transferred to the caller.
+    pub fn into_inner(mut this: Self) -> T {
+        let value = unsafe { ManuallyDrop::take(&mut this.inner) };
+        mem::forget(this);
+        value
+    }
+}
+
+impl<'a, T: Send + 'static> DeferDropIn<'a, T> {
+    /// Get the [`GarbageCan`] that this value will be sent to.
+    #[inline]
+    pub fn garbage_can(this: &Self) -> &'a GarbageCan {
+        this.executor
+    }
+
+    /// Drop the `DeferDropIn`, returning a [`DropHandle`] that can be used to
+    /// find out when the inner value has actually been dropped by the
+    /// background thread.
+    pub fn drop_with_handle(this: Self) -> DropHandle {
+        let can = this.executor;
+        let (value, handle) = handle::with_handle(Self::into_inner(this));
+        can.throw_away_garbage(
+            Garbage::with_type_name(value, any::type_name::<T>()),
+            Priority::Normal,
+        );
+        can.flush_local();
+        handle
+    }
+
+    /// Drop the `DeferDropIn`, but keep the inner value alive in the
+    /// background thread until `delay` has passed. See
+    /// [`GarbageCan::throw_away_at`] for details.
+    #[inline]
+    pub fn drop_after(this: Self, delay: Duration) {
+        let can = this.executor;
+        can.throw_away_after(Self::into_inner(this), delay);
+    }
+
+    /// Drop the `DeferDropIn`, but keep the inner value alive in the
+    /// background thread until `deadline` has passed. See
+    /// [`GarbageCan::throw_away_at`] for details.
+    #[inline]
+    pub fn drop_at(this: Self, deadline: Instant) {
+        let can = this.executor;
+        can.throw_away_at(Self::into_inner(this), deadline);
+    }
+}
+
+impl<T: Send + 'static, E: DropExecutor + ?Sized> Drop for DeferDropIn<'_, T, E> {
+    fn drop(&mut self) {
+        let value = unsafe { ManuallyDrop::take(&mut self.inner) };
+        self.executor.execute(Garbage::new(value));
+    }
+}
+
+impl<T: Send + 'static, E: DropExecutor + ?Sized> AsRef<T> for DeferDropIn<'_, T, E> {
+    #[inline]
+    fn as_ref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T: Send + 'static, E: DropExecutor + ?Sized> AsMut<T> for DeferDropIn<'_, T, E> {
+    #[inline]
+    fn as_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+impl<T: Send + 'static, E: DropExecutor + ?Sized> Deref for DeferDropIn<'_, T, E> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl<T: Send + 'static, E: DropExecutor + ?Sized> DerefMut for DeferDropIn<'_, T, E> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner
+    }
+}
+
+impl<T: Send + 'static + Clone, E: DropExecutor + ?Sized> Clone for DeferDropIn<'_, T, E> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self::new(T::clone(&self.inner), self.executor)
+    }
+}
+
+impl<T: Send + 'static + fmt::Debug, E: DropExecutor + ?Sized> fmt::Debug
+    for DeferDropIn<'_, T, E>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeferDropIn")
+            .field("inner", &*self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: Send + 'static + PartialEq, E: DropExecutor + ?Sized> PartialEq for DeferDropIn<'_, T, E> {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        *self.inner == *other.inner
+    }
+}
+
+impl<T: Send + 'static + Eq, E: DropExecutor + ?Sized> Eq for DeferDropIn<'_, T, E> {}
+
+impl<T: Send + 'static + PartialOrd, E: DropExecutor + ?Sized> PartialOrd
+    for DeferDropIn<'_, T, E>
+{
+    #[inline]
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        T::partial_cmp(&self.inner, &other.inner)
+    }
+}
+
+impl<T: Send + 'static + Ord, E: DropExecutor + ?Sized> Ord for DeferDropIn<'_, T, E> {
+    #[inline]
+    fn cmp(&self, other: &Self) -> Ordering {
+        T::cmp(&self.inner, &other.inner)
+    }
+}
+
+impl<T: Send + 'static + Hash, E: DropExecutor + ?Sized> Hash for DeferDropIn<'_, T, E> {
+    #[inline]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.inner.hash(state)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T: Serialize + Send + 'static, E: DropExecutor + ?Sized> Serialize for DeferDropIn<'_, T, E> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.as_ref().serialize(serializer)
+    }
+}
+
+/// The policy used by a bounded garbage queue when it's full. See
+/// [`Builder::bounded`] for details.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Overflow {
+    /// Block the dropping thread until there is room in the queue. This
+    /// applies backpressure to threads that produce garbage faster than it
+    /// can be dropped.
+    Block,
+
+    /// Drop the value inline, on the dropping thread, as though it had never
+    /// been wrapped in a [`DeferDrop`]. Delayed values, which mustn't be
+    /// dropped before their deadline, block until there is room instead.
+    DropInline,
+
+    /// Send the value to a secondary, unbounded queue. The background thread
+    /// consumes both queues, so this mode never blocks; it only bounds the
+    /// memory used by the primary queue. Values that spill over are no longer
+    /// guaranteed to be dropped in the order they were sent.
+    Spill,
+}
+
+/// What a background thread does when a destructor panics. See
+/// [`Builder::on_panic`] for details.
+#[derive(Clone, Default)]
+pub enum PanicPolicy {
+    /// Print a message to stderr and abort the process.
+    #[default]
+    Abort,
+
+    /// Print a message to stderr, and carry on dropping garbage.
+    Log,
+
+    /// Call this function with the panic payload (the value passed to
+    /// [`panic!`]), and carry on dropping garbage. If the function itself
+    /// panics, a message is printed to stderr, and the background thread
+    /// carries on anyway.
+    Hook(Arc<dyn Fn(Box<dyn Any + Send>) + Send + Sync>),
+}
+
+impl PanicPolicy {
+    fn handle(&self, payload: Box<dyn Any + Send>) {
+        match self {
+            PanicPolicy::Abort => {
+                eprintln!("defer-drop: a destructor panicked in a background thread; aborting");
+                process::abort()
+            }
+            PanicPolicy::Log => {
+                eprintln!("defer-drop: a destructor panicked in a background thread; restarting it")
+            }
+            PanicPolicy::Hook(hook) => {
+                // Don't let the hook take down the background thread; the
+                // other background threads (and anyone flushing) are
+                // counting on it.
+                if panic::catch_unwind(AssertUnwindSafe(|| hook(payload))).is_err() {
+                    eprintln!(
+                        "defer-drop: a panic hook panicked in a background thread; ignoring it"
+                    )
+                }
+            }
+        }
     }
 }
 
-struct GarbageCan {
-    sender: Sender<Box<dyn Send>>,
-    handle: JoinHandle<()>,
+impl fmt::Debug for PanicPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PanicPolicy::Abort => f.write_str("Abort"),
+            PanicPolicy::Log => f.write_str("Log"),
+            PanicPolicy::Hook(_) => f.write_str("Hook(..)"),
+        }
+    }
 }
 
-impl GarbageCan {
-    fn new(name: String) -> Self {
-        let (sender, receiver) = channel::unbounded();
+/// Error returned when the global garbage can couldn't be initialized.
+#[derive(Debug)]
+pub enum InitError {
+    /// The global garbage can was already initialized, either by a previous
+    /// call to [`Builder::init`], or because a [`DeferDrop`] was already
+    /// dropped.
+    AlreadyInitialized,
 
-        // TODO: drops should never panic, but if one does, we should
-        // probably abort the process
-        let handle = thread::Builder::new()
-            .name(name)
-            .spawn(move || receiver.into_iter().for_each(drop))
-            .expect("failed to spawn defer-drop background thread");
+    /// A background thread couldn't be spawned.
+    Spawn(io::Error),
+}
 
-        Self { sender, handle }
+impl fmt::Display for InitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InitError::AlreadyInitialized => {
+                f.write_str("the defer-drop background thread was already initialized")
+            }
+            InitError::Spawn(_) => f.write_str("failed to spawn defer-drop background thread"),
+        }
     }
+}
 
-    fn throw_away<T: Send + 'static>(&self, value: T) {
-        // Only send to the garbage can if we're not currently in the garbage
-        // can; if we are, just drop it eagerly.
-        if thread::current().id() != self.handle.thread().id() {
-            let boxed = Box::new(value);
-            self.sender.send(boxed).unwrap();
+impl Error for InitError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            InitError::AlreadyInitialized => None,
+            InitError::Spawn(err) => Some(err),
         }
     }
 }
 
+/// Builder for configuring a [`GarbageCan`], including the global one used by
+/// [`DeferDrop`].
+///
+/// The global garbage can is normally created lazily, the first time a
+/// [`DeferDrop`] is dropped. Use [`Builder::init`] at the start of your
+/// program, before any `DeferDrop` has been dropped, to customize it. Use
+/// [`Builder::build`] to create a separate garbage can of your own.
+///
+/// # Example
+///
+/// ```
+/// use defer_drop::{Builder, DeferDrop, InitError, Overflow};
+///
+/// let _guard = Builder::new()
+///     .name("garbage collector")
+///     .stack_size(64 * 1024)
+///     .bounded(1024, Overflow::Block)
+///     .threads(2)
+///     .init()
+///     .expect("defer-drop was already initialized");
+///
+/// drop(DeferDrop::new(vec![1, 2, 3]));
+///
+/// // The global garbage can can only be configured once
+/// assert!(matches!(
+///     Builder::new().init(),
+///     Err(InitError::AlreadyInitialized)
+/// ));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Builder {
+    name: String,
+    stack_size: Option<usize>,
+    bound: Option<(usize, Overflow)>,
+    threads: usize,
+    ordered: bool,
+    on_panic: PanicPolicy,
+    profile_types: bool,
+    cost_threshold: usize,
+    batch_size: usize,
+    slice_size: usize,
+    manual: bool,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self {
+            name: "defer-drop background thread".to_owned(),
+            stack_size: None,
+            bound: None,
+            threads: 1,
+            ordered: false,
+            on_panic: PanicPolicy::Abort,
+            profile_types: false,
+            cost_threshold: 64,
+            batch_size: 1,
+            slice_size: 1024,
+            manual: false,
+        }
+    }
+}
+
+impl Builder {
+    /// Create a new `Builder` with the default configuration.
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the name of the background thread. If there are several
+    /// [background threads][Builder::threads], each of them gets this name
+    /// followed by its index.
+    #[inline]
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Set the stack size, in bytes, of the background threads. By default,
+    /// this is the same as for any other thread spawned by the standard
+    /// library; see [`std::thread::Builder::stack_size`] for details.
+    #[inline]
+    pub fn stack_size(mut self, size: usize) -> Self {
+        self.stack_size = Some(size);
+        self
+    }
+
+    /// Bound the garbage queue to `capacity` values. When the queue is full,
+    /// `overflow` decides what happens to additional garbage. By default, the
+    /// queue is unbounded.
+    ///
+    /// In [ordered][Builder::ordered] mode, each background thread has its
+    /// own queue, and each of them is bounded to `capacity`.
+    #[inline]
+    pub fn bounded(mut self, capacity: usize, overflow: Overflow) -> Self {
+        self.bound = Some((capacity, overflow));
+        self
+    }
+
+    /// Set the number of background threads that drop garbage. By default,
+    /// there is only one. With more than one, a slow destructor only stalls
+    /// the thread that's running it, rather than all deferred drops.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `threads` is 0.
+    #[inline]
+    pub fn threads(mut self, threads: usize) -> Self {
+        assert!(
+            threads > 0,
+            "defer-drop needs at least one background thread"
+        );
+        self.threads = threads;
+        self
+    }
+
+    /// Preserve the drop order of values sent from each thread, even when
+    /// there are several [background threads][Builder::threads].
+    ///
+    /// By default, all of the background threads share a single queue, which
+    /// means that values sent from a single thread may be dropped
+    /// concurrently and in any order. In ordered mode, each background thread
+    /// has its own queue, and each sending thread always uses the same one,
+    /// so values from a single thread are dropped in the order they were
+    /// sent (as long as they were sent with the same [priority]). The
+    /// tradeoff is that a slow destructor stalls everything queued behind
+    /// it, just like with a single background thread.
+    ///
+    /// This has no effect if there is only one background thread, since the
+    /// order is always preserved in that case.
+    #[inline]
+    pub fn ordered(mut self, ordered: bool) -> Self {
+        self.ordered = ordered;
+        self
+    }
+
+    /// Choose what happens when a destructor panics in one of the background
+    /// threads. By default, the process is aborted; see [`PanicPolicy`] for
+    /// the alternatives.
+    ///
+    /// If the panic is caught, the background thread that caught it is
+    /// replaced with a fresh one, in case the destructor left any
+    /// thread-local state in a bad way.
+    #[inline]
+    pub fn on_panic(mut self, policy: PanicPolicy) -> Self {
+        self.on_panic = policy;
+        self
+    }
+
+    /// Call `hook` with the payload whenever a destructor panics in one of
+    /// the background threads. This is shorthand for
+    /// [`on_panic`][Builder::on_panic] with [`PanicPolicy::Hook`].
+    #[inline]
+    pub fn panic_hook(self, hook: impl Fn(Box<dyn Any + Send>) + Send + Sync + 'static) -> Self {
+        self.on_panic(PanicPolicy::Hook(Arc::new(hook)))
+    }
+
+    /// Keep track of how long the background threads spend dropping each
+    /// type of value, so that you can find out which types are expensive to
+    /// drop (and whether it's worth deferring them). Get the results with
+    /// [`type_stats`] or [`GarbageCan::type_stats`]. This is disabled by
+    /// default, because it adds some overhead to every drop.
+    #[inline]
+    pub fn profile_types(mut self, profile: bool) -> Self {
+        self.profile_types = profile;
+        self
+    }
+
+    /// Set the [estimated cost][DropCost] at which [`AdaptiveDeferDrop`]
+    /// and [`GarbageCan::throw_away_if_costly`] send values to the background
+    /// threads; cheaper values are dropped inline. The default is 64.
+    #[inline]
+    pub fn cost_threshold(mut self, threshold: usize) -> Self {
+        self.cost_threshold = threshold;
+        self
+    }
+
+    /// Batch up values in each sending thread, and send them to the
+    /// background threads `size` at a time, rather than one by one. This cuts
+    /// down on contention for the queue when many threads are dropping lots
+    /// of values. The default is 1, which disables batching.
+    ///
+    /// A thread's batch is sent when it's full, when the thread calls
+    /// [`flush_local`] (or [`flush`]), and when the thread exits. Until then,
+    /// the values in it haven't been dropped, so a thread that holds on to a
+    /// partial batch for a long time holds on to those values too.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is 0.
+    #[inline]
+    pub fn batch_size(mut self, size: usize) -> Self {
+        assert!(size > 0, "defer-drop batch size must be at least 1");
+        self.batch_size = size;
+        self
+    }
+
+    /// Set the number of pieces of an [incrementally dropped][IncrementalDrop]
+    /// value that a background thread drops at a time, before checking for
+    /// other garbage. Smaller slices mean less latency for everything else,
+    /// at the cost of some overhead. The default is 1024.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is 0.
+    #[inline]
+    pub fn slice_size(mut self, size: usize) -> Self {
+        assert!(size > 0, "defer-drop slice size must be at least 1");
+        self.slice_size = size;
+        self
+    }
+
+    /// Don't spawn any background threads. Instead, garbage waits in the
+    /// queue until the application drops it, on a thread of its choosing,
+    /// with [`GarbageCan::drain_for`] or [`GarbageCan::drain_n`] (or
+    /// [`drain_for`] and [`drain_n`], for the global garbage can). This is
+    /// useful for UI and game loops, which can collect garbage during idle
+    /// time, without competing with a background thread.
+    ///
+    /// In manual mode, [`flush`][GarbageCan::flush] drains everything in the
+    /// queue on the calling thread, and so does shutting down the garbage
+    /// can. The [number of threads][Builder::threads] and
+    /// [ordered mode][Builder::ordered] are ignored. Destructor panics are
+    /// handled according to the [panic policy][Builder::on_panic], just like
+    /// in a background thread.
+    ///
+    /// Since nothing makes room in a [bounded][Builder::bounded] queue until
+    /// it's drained, [`Overflow::Block`] would block forever in manual mode;
+    /// it's treated as [`Overflow::DropInline`] instead.
+    ///
+    /// ```
+    /// use defer_drop::Builder;
+    /// use std::time::Duration;
+    ///
+    /// let can = Builder::new().manual(true).build().unwrap();
+    ///
+    /// for i in 0..10 {
+    ///     drop(can.defer(vec![i; 100]));
+    /// }
+    ///
+    /// // Nothing is dropped until we get around to it
+    /// assert_eq!(can.stats().queue_len, 10);
+    /// assert_eq!(can.drain_n(3), 3);
+    /// assert_eq!(can.stats().queue_len, 7);
+    ///
+    /// // Spend up to a millisecond on the rest
+    /// can.drain_for(Duration::from_millis(1));
+    /// ```
+    #[inline]
+    pub fn manual(mut self, manual: bool) -> Self {
+        self.manual = manual;
+        self
+    }
+
+    /// Create a new [`GarbageCan`] with this configuration, separate from the
+    /// global one. Returns an error if any of the background threads couldn't
+    /// be spawned.
+    pub fn build(self) -> io::Result<GarbageCan> {
+        GarbageCan::from_builder(&self)
+    }
+
+    /// Initialize the global background thread with this configuration.
+    /// Returns an error if it was already initialized, either by a previous
+    /// call to `init` or because a [`DeferDrop`] was already dropped, or if
+    /// a background thread couldn't be spawned. In the latter case, the
+    /// global garbage can is left uninitialized.
+    ///
+    /// On success, returns a [`DropGuard`] which shuts down the background
+    /// thread when it's dropped, unless there are other guards; see its
+    /// documentation for details.
+    pub fn init(self) -> Result<DropGuard, InitError> {
+        let mut fresh = false;
+
+        GARBAGE_CAN
+            .get_or_try_init(|| {
+                fresh = true;
+                self.build()
+            })
+            .map_err(InitError::Spawn)?;
+
+        match fresh {
+            true => Ok(DropGuard::new()),
+            false => Err(InitError::AlreadyInitialized),
+        }
+    }
+}
+
+/// Initialize the global background thread with the default configuration,
+/// if it isn't already running, and return a [`DropGuard`] for it.
+///
+/// Typically you'll call this at the top of `main`, so that everything
+/// dropped during the life of the program is destroyed before it exits:
+///
+/// ```
+/// use defer_drop::DeferDrop;
+///
+/// let _guard = defer_drop::init();
+///
+/// drop(DeferDrop::new(vec![1, 2, 3]));
+///
+/// // `_guard` is dropped here, which waits for the vector to be dropped
+/// ```
+///
+/// It's fine to call this more than once; the background thread keeps
+/// running until every [`DropGuard`] has been dropped.
+///
+/// If the background thread can't be spawned, [`DeferDrop`] values are
+/// dropped inline instead. Use [`try_init`] to find out if that happens.
+pub fn init() -> DropGuard {
+    global_garbage_can();
+    DropGuard::new()
+}
+
+/// Like [`init`], but return an error if the background thread can't be
+/// spawned. In that case, the global garbage can is left uninitialized.
+pub fn try_init() -> io::Result<DropGuard> {
+    GARBAGE_CAN.get_or_try_init(|| Builder::new().build())?;
+    Ok(DropGuard::new())
+}
+
+/// Guard that shuts down the global background thread when it's dropped.
+///
+/// Normally, there's no guarantee that deferred values will be dropped at
+/// all: if `main` returns while they're still queued, they're silently lost.
+/// When a `DropGuard` is dropped, it closes the garbage queue and waits for
+/// the background thread to destroy everything in it. After that, any
+/// [`DeferDrop`] values are dropped inline, on the thread that drops them.
+///
+/// Create one with [`init`] or [`Builder::init`]. If there's more than one
+/// (say, because a library calls [`init`] too), the background thread is
+/// shut down when the last of them is dropped:
+///
+/// ```
+/// use defer_drop::DeferDrop;
+///
+/// let guard = defer_drop::init();
+///
+/// // Somewhere else, another guard comes and goes
+/// drop(defer_drop::init());
+///
+/// // The background thread is still running
+/// drop(DeferDrop::new(vec![1, 2, 3]));
+/// assert_eq!(defer_drop::stats().inline_drops, 0);
+///
+/// // Now it's shut down
+/// drop(guard);
+/// drop(DeferDrop::new(vec![1, 2, 3]));
+/// assert_eq!(defer_drop::stats().inline_drops, 1);
+/// ```
+#[must_use = "the background thread is shut down when the DropGuard is dropped"]
+#[derive(Debug)]
+pub struct DropGuard {
+    _private: (),
+}
+
+/// The number of [`DropGuard`]s that haven't been dropped yet.
+static DROP_GUARDS: AtomicUsize = AtomicUsize::new(0);
+
+impl DropGuard {
+    fn new() -> Self {
+        DROP_GUARDS.fetch_add(1, AtomicOrdering::AcqRel);
+        DropGuard { _private: () }
+    }
+}
+
+impl Drop for DropGuard {
+    fn drop(&mut self) {
+        if DROP_GUARDS.fetch_sub(1, AtomicOrdering::AcqRel) > 1 {
+            return;
+        }
+
+        if let Some(can) = GARBAGE_CAN.get() {
+            can.shutdown();
+        }
+    }
+}
+
+/// Block until every value that was dropped (by this thread, or by any other
+/// thread before this call) has been destroyed by the global background
+/// thread.
+///
+/// This is useful for tests, and for shutdown paths that need to be sure that
+/// some resource (like a file handle or a temporary directory) has really
+/// been released. If it's called from the background thread itself (for
+/// instance, in the destructor of a deferred value), it returns immediately,
+/// since waiting would deadlock.
+///
+/// With [`Builder::batch_size`], this sends this thread's batch first, but
+/// values still sitting in other threads' batches aren't included.
+pub fn flush() {
+    if let Some(can) = GARBAGE_CAN.get() {
+        can.flush();
+    }
+}
+
+/// Send any values that this thread has batched up for the global background
+/// thread, without waiting for the batch to fill up. This only matters if
+/// the global garbage can was initialized with [`Builder::batch_size`].
+pub fn flush_local() {
+    if let Some(can) = GARBAGE_CAN.get() {
+        can.flush_local();
+    }
+}
+
+/// Like [`flush`], but give up after `timeout`. Returns `true` if all of the
+/// queued values were destroyed in time.
+pub fn flush_timeout(timeout: Duration) -> bool {
+    match GARBAGE_CAN.get() {
+        None => true,
+        Some(can) => can.flush_timeout(timeout),
+    }
+}
+
+/// Drop values from the global garbage can's queue on the current thread,
+/// until it's empty or `budget` has been used up, and return the number of
+/// values that were dropped. This only does anything if the global garbage
+/// can was initialized in [manual mode][Builder::manual]; see
+/// [`GarbageCan::drain_for`] for details.
+///
+/// ```
+/// use defer_drop::{Builder, DeferDrop};
+/// use std::time::Duration;
+///
+/// let _guard = Builder::new().manual(true).init().unwrap();
+///
+/// drop(DeferDrop::new(vec![1, 2, 3]));
+///
+/// // Later, when the application is idle
+/// assert_eq!(defer_drop::drain_for(Duration::from_millis(5)), 1);
+/// ```
+pub fn drain_for(budget: Duration) -> usize {
+    GARBAGE_CAN.get().map_or(0, |can| can.drain_for(budget))
+}
+
+/// Drop up to `count` values from the global garbage can's queue on the
+/// current thread, and return the number of values that were dropped. This
+/// only does anything if the global garbage can was initialized in
+/// [manual mode][Builder::manual]; see [`GarbageCan::drain_n`] for details.
+pub fn drain_n(count: usize) -> usize {
+    GARBAGE_CAN.get().map_or(0, |can| can.drain_n(count))
+}
+
+/// Get a snapshot of the global garbage can's activity. See [`Stats`] for
+/// details. If the global garbage can hasn't been initialized yet, all of
+/// the counters are zero.
+pub fn stats() -> Stats {
+    GARBAGE_CAN.get().map(GarbageCan::stats).unwrap_or_default()
+}
+
+/// Get a breakdown of the time the global garbage can has spent dropping
+/// each type of value. See [`TypeStats`] for details. This is only available
+/// if the global garbage can was initialized with [`Builder::profile_types`];
+/// otherwise, it's empty.
+pub fn type_stats() -> Vec<TypeStats> {
+    GARBAGE_CAN
+        .get()
+        .map(GarbageCan::type_stats)
+        .unwrap_or_default()
+}
+
 #[cfg(test)]
 mod tests {
     use crossbeam_channel as channel;
-    use std::{
-        sync::{Arc, Mutex},
-        thread,
-        time::Duration,
-    };
+    use std::{thread, time::Duration};
 
-    use crate::DeferDrop;
+    use crate::{
+        test_util::{Record, Recorder, ThreadReporter},
+        DeferDrop,
+    };
 
     #[test]
     fn test() {
-        /// This struct, when dropped, reports the thread ID of its dropping
-        /// thread to the channel
-        struct ThreadReporter {
-            chan: channel::Sender<thread::ThreadId>,
-        }
-
-        impl Drop for ThreadReporter {
-            fn drop(&mut self) {
-                self.chan.send(thread::current().id()).unwrap();
-            }
-        }
-
         let (sender, receiver) = channel::bounded(1);
         let this_thread_id = thread::current().id();
 
@@ -245,72 +1102,42 @@ mod tests {
     // dropped locally (that is, they don't re-send into the channel)
     #[test]
     fn test_no_recursive_send() {
-        #[allow(dead_code)]
-        struct DropOrderRecorder<T> {
-            id: u32,
-            value: T,
-            record: Arc<Mutex<Vec<u32>>>,
-        }
+        let record: Record<u32> = Default::default();
+        let leaf = |id| DeferDrop::new(Recorder::new(id, &record));
 
-        impl<T> Drop for DropOrderRecorder<T> {
-            fn drop(&mut self) {
-                self.record.lock().unwrap().push(self.id)
-            }
-        }
-
-        let drop_order_record: Arc<Mutex<Vec<u32>>> = Default::default();
-
-        let value = DeferDrop::new(DropOrderRecorder {
-            id: 0,
-            record: drop_order_record.clone(),
-            value: [
-                DeferDrop::new(DropOrderRecorder {
-                    id: 1,
-                    record: drop_order_record.clone(),
-                    value: [
-                        DeferDrop::new(DropOrderRecorder {
-                            id: 2,
-                            record: drop_order_record.clone(),
-                            value: (),
-                        }),
-                        DeferDrop::new(DropOrderRecorder {
-                            id: 3,
-                            record: drop_order_record.clone(),
-                            value: (),
-                        }),
-                    ],
-                }),
-                DeferDrop::new(DropOrderRecorder {
-                    id: 4,
-                    record: drop_order_record.clone(),
-                    value: [
-                        DeferDrop::new(DropOrderRecorder {
-                            id: 5,
-                            record: drop_order_record.clone(),
-                            value: (),
-                        }),
-                        DeferDrop::new(DropOrderRecorder {
-                            id: 6,
-                            record: drop_order_record.clone(),
-                            value: (),
-                        }),
-                    ],
-                }),
+        let value = DeferDrop::new(Recorder::with_children(
+            0,
+            &record,
+            [
+                DeferDrop::new(Recorder::with_children(1, &record, [leaf(2), leaf(3)])),
+                DeferDrop::new(Recorder::with_children(4, &record, [leaf(5), leaf(6)])),
             ],
-        });
+        ));
 
         drop(value);
+        crate::flush();
 
-        loop {
-            thread::yield_now();
-            let lock = drop_order_record.lock().unwrap();
-            if lock.len() >= 7 {
-                break;
-            }
-        }
-
-        let lock = drop_order_record.lock().unwrap();
+        let lock = record.lock().unwrap();
 
         assert_eq!(lock.as_slice(), [0, 1, 2, 3, 4, 5, 6])
     }
+
+    #[test]
+    fn test_drop_with_handle() {
+        let (sender, receiver) = channel::bounded(1);
+
+        let thing = DeferDrop::new(ThreadReporter { chan: sender });
+        let handle = DeferDrop::drop_with_handle(thing);
+
+        assert!(
+            handle.wait_timeout(Duration::from_secs(1)),
+            "thing wasn't dropped within one second of being dropped"
+        );
+
+        assert_ne!(
+            receiver.try_recv().unwrap(),
+            thread::current().id(),
+            "thing wasn't dropped in a different thread"
+        );
+    }
 }
@@ -0,0 +1,251 @@
+/*!
+Deferred drops for values that aren't [`Send`], on the thread that owns them.
+
+A [`DeferDrop`][crate::DeferDrop] sends its value to another thread, so it
+can't hold an `Rc`, or anything else that has to stay on its own thread. A
+[`LocalDeferDrop`] doesn't go anywhere: when it's dropped, its value is added
+to a queue belonging to the current thread, and dropped later, when the
+application calls [`drain`] (say, at the end of a frame or a request), or
+[`drain_for`] with a time budget. That way, expensive drops can still be moved
+off of the latency-critical path, even for single-threaded types.
+
+Anything still in a thread's queue is dropped when the thread exits.
+
+# Example
+
+```
+use std::rc::Rc;
+use defer_drop::local::{self, LocalDeferDrop};
+
+let graph = LocalDeferDrop::new(vec![Rc::new(String::from("Hello")); 1000]);
+assert_eq!(graph.len(), 1000);
+
+// This is cheap; the vector is just added to the queue
+drop(graph);
+assert_eq!(local::pending(), 1);
+
+// Later, when there's time
+assert_eq!(local::drain(), 1);
+assert_eq!(local::pending(), 0);
+```
+*/
+
+use std::{
+    any::Any,
+    cell::RefCell,
+    collections::VecDeque,
+    mem::{self, ManuallyDrop},
+    ops::{Deref, DerefMut},
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+thread_local! {
+    /// This thread's deferred values, oldest first.
+    static QUEUE: RefCell<VecDeque<Box<dyn Any>>> = const { RefCell::new(VecDeque::new()) };
+}
+
+/// Add a value to the current thread's queue, to be dropped by a later call
+/// to [`drain`] or [`drain_for`]. If the thread is already exiting, the value
+/// is dropped immediately instead.
+pub fn throw_away<T: 'static>(value: T) {
+    let mut value = Some(value);
+
+    let _ = QUEUE.try_with(|queue| {
+        if let Some(value) = value.take() {
+            queue.borrow_mut().push_back(Box::new(value));
+        }
+    });
+}
+
+/// Remove the oldest value from the current thread's queue. The queue isn't
+/// borrowed while the value is dropped, since its destructor might defer
+/// values of its own.
+fn pop() -> Option<Box<dyn Any>> {
+    QUEUE
+        .try_with(|queue| queue.borrow_mut().pop_front())
+        .ok()
+        .flatten()
+}
+
+/// Drop everything in the current thread's queue, including anything that's
+/// deferred while doing so, and return the number of values that were
+/// dropped.
+pub fn drain() -> usize {
+    let mut count = 0;
+
+    while let Some(value) = pop() {
+        drop(value);
+        count += 1;
+    }
+
+    count
+}
+
+/// Drop values from the current thread's queue, oldest first, until it's
+/// empty or `budget` has been used up, and return the number of values that
+/// were dropped.
+///
+/// The budget is checked between drops, so it can be overrun by however long
+/// the last value takes to drop. At least one value is dropped, if there are
+/// any, so that a small budget still makes progress.
+pub fn drain_for(budget: Duration) -> usize {
+    let deadline = Instant::now() + budget;
+    let mut count = 0;
+
+    while let Some(value) = pop() {
+        drop(value);
+        count += 1;
+
+        if Instant::now() >= deadline {
+            break;
+        }
+    }
+
+    count
+}
+
+/// The number of values waiting in the current thread's queue.
+pub fn pending() -> usize {
+    QUEUE.try_with(|queue| queue.borrow().len()).unwrap_or(0)
+}
+
+/// Wrapper type that, when dropped, adds the inner value to the current
+/// thread's queue, to be dropped later by [`drain`] or [`drain_for`]. See the
+/// [module documentation][self] for details.
+///
+/// Unlike [`DeferDrop`][crate::DeferDrop], the inner value doesn't need to be
+/// [`Send`]; and since the queue belongs to a thread, neither is a
+/// `LocalDeferDrop` of a `!Send` value.
+#[repr(transparent)]
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct LocalDeferDrop<T: 'static> {
+    inner: ManuallyDrop<T>,
+}
+
+impl<T: 'static> LocalDeferDrop<T> {
+    /// Create a new `LocalDeferDrop` value.
+    #[inline]
+    pub fn new(value: T) -> Self {
+        LocalDeferDrop {
+            inner: ManuallyDrop::new(value),
+        }
+    }
+
+    /// Unwrap the `LocalDeferDrop`, returning the inner value. This has the
+    /// effect of cancelling the deferred drop behavior; ownership of the
+    /// inner value is transferred to the caller.
+    pub fn into_inner(mut this: Self) -> T {
+        let value = unsafe { ManuallyDrop::take(&mut this.inner) };
+        mem::forget(this);
+        value
+    }
+}
+
+impl<T: 'static> Drop for LocalDeferDrop<T> {
+    fn drop(&mut self) {
+        throw_away(unsafe { ManuallyDrop::take(&mut self.inner) });
+    }
+}
+
+impl<T: 'static> From<T> for LocalDeferDrop<T> {
+    #[inline]
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<T: 'static> AsRef<T> for LocalDeferDrop<T> {
+    #[inline]
+    fn as_ref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T: 'static> AsMut<T> for LocalDeferDrop<T> {
+    #[inline]
+    fn as_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+impl<T: 'static> Deref for LocalDeferDrop<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl<T: 'static> DerefMut for LocalDeferDrop<T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T: Serialize + 'static> Serialize for LocalDeferDrop<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.as_ref().serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T: Deserialize<'de> + 'static> Deserialize<'de> for LocalDeferDrop<T> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        T::deserialize(deserializer).map(Self::new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{rc::Rc, time::Duration};
+
+    use super::{drain, drain_for, pending, LocalDeferDrop};
+    use crate::test_util::{Record, Recorder};
+
+    #[test]
+    fn test_drain() {
+        let record: Record<u32> = Default::default();
+        let leaf = |id| LocalDeferDrop::new(Recorder::new(id, &record));
+
+        drop(LocalDeferDrop::new(Recorder::with_children(
+            0,
+            &record,
+            vec![leaf(1), leaf(2)],
+        )));
+
+        assert_eq!(pending(), 1);
+        assert!(record.lock().unwrap().is_empty());
+
+        // The children are deferred by the parent's destructor, and drained
+        // along with it
+        assert_eq!(drain(), 3);
+        assert_eq!(pending(), 0);
+        assert_eq!(record.lock().unwrap().as_slice(), [0, 1, 2]);
+    }
+
+    #[test]
+    fn test_drain_for() {
+        for _ in 0..3 {
+            drop(LocalDeferDrop::new(Rc::new(())));
+        }
+
+        // A zero budget still drops one value
+        assert_eq!(drain_for(Duration::ZERO), 1);
+        assert_eq!(pending(), 2);
+
+        assert_eq!(drain_for(Duration::from_secs(60)), 2);
+        assert_eq!(pending(), 0);
+    }
+}
@@ -0,0 +1,153 @@
+use std::{
+    mem::{self, ManuallyDrop},
+    ops::{Deref, DerefMut},
+};
+
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::global_garbage_can;
+
+#[cfg(doc)]
+use crate::{DeferDrop, GarbageCan};
+
+/// A collection that drops its elements in parallel, on the rayon thread
+/// pool, when it's dropped.
+pub(crate) struct Parallel<T: IntoParallelIterator> {
+    collection: Option<T>,
+}
+
+impl<T: IntoParallelIterator> Parallel<T> {
+    pub fn new(collection: T) -> Self {
+        Self {
+            collection: Some(collection),
+        }
+    }
+}
+
+impl<T: IntoParallelIterator> Drop for Parallel<T> {
+    fn drop(&mut self) {
+        if let Some(collection) = self.collection.take() {
+            collection.into_par_iter().for_each(drop);
+        }
+    }
+}
+
+/// Wrapper type that, when dropped, sends the inner collection to the global
+/// background thread, which then drops its elements in parallel on the
+/// [rayon] thread pool. Requires the `rayon` feature.
+///
+/// A single huge collection, like a `Vec<Vec<String>>`, can keep the
+/// background thread busy for a long time if it's dropped serially. Since
+/// the elements are independent of each other, a `ParallelDeferDrop` splits
+/// the collection up and drops the pieces across all of the rayon threads.
+/// The background thread waits for them to finish, so
+/// [`flush`][crate::flush] and [`DropGuard`][crate::DropGuard] still work as
+/// usual.
+///
+/// This works for any collection that implements [`IntoParallelIterator`],
+/// which includes all of the standard collections. Use
+/// [`GarbageCan::throw_away_parallel`] to do the same with a garbage can of
+/// your own.
+///
+/// # Example
+///
+/// ```
+/// use defer_drop::ParallelDeferDrop;
+///
+/// let value = ParallelDeferDrop::new(vec![vec![String::from("Hello"); 1000]; 1000]);
+/// drop(value);
+///
+/// defer_drop::flush();
+/// ```
+#[repr(transparent)]
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ParallelDeferDrop<T: IntoParallelIterator + Send + 'static> {
+    inner: ManuallyDrop<T>,
+}
+
+impl<T: IntoParallelIterator + Send + 'static> ParallelDeferDrop<T> {
+    /// Create a new `ParallelDeferDrop` value.
+    #[inline]
+    pub fn new(value: T) -> Self {
+        ParallelDeferDrop {
+            inner: ManuallyDrop::new(value),
+        }
+    }
+
+    /// Unwrap the `ParallelDeferDrop`, returning the inner value. This has
+    /// the effect of cancelling the deferred drop behavior; ownership of the
+    /// inner value is transferred to the caller.
+    pub fn into_inner(mut this: Self) -> T {
+        let value = unsafe { ManuallyDrop::take(&mut this.inner) };
+        mem::forget(this);
+        value
+    }
+}
+
+impl<T: IntoParallelIterator + Send + 'static> Drop for ParallelDeferDrop<T> {
+    fn drop(&mut self) {
+        global_garbage_can().throw_away_parallel(unsafe { ManuallyDrop::take(&mut self.inner) });
+    }
+}
+
+impl<T: IntoParallelIterator + Send + 'static> From<T> for ParallelDeferDrop<T> {
+    #[inline]
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<T: IntoParallelIterator + Send + 'static> AsRef<T> for ParallelDeferDrop<T> {
+    #[inline]
+    fn as_ref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T: IntoParallelIterator + Send + 'static> AsMut<T> for ParallelDeferDrop<T> {
+    #[inline]
+    fn as_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+impl<T: IntoParallelIterator + Send + 'static> Deref for ParallelDeferDrop<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl<T: IntoParallelIterator + Send + 'static> DerefMut for ParallelDeferDrop<T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T: IntoParallelIterator + Serialize + Send + 'static> Serialize for ParallelDeferDrop<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.as_ref().serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T: IntoParallelIterator + Deserialize<'de> + Send + 'static> Deserialize<'de>
+    for ParallelDeferDrop<T>
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        T::deserialize(deserializer).map(Self::new)
+    }
+}
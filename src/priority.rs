@@ -0,0 +1,88 @@
+/*!
+Priority levels for deferred drops.
+
+Each garbage queue is really three queues, one for each [`Priority`]. The
+background threads always drop high priority garbage first, and low priority
+garbage only when there's nothing else to do. This is useful for values that
+hold on to some resource, like a file or a socket, which should be released
+promptly even if there's a lot of bulk garbage queued up ahead of them.
+
+[`DeferDrop`][crate::DeferDrop] takes its priority as a type parameter, one of
+the [`High`], [`Normal`] (the default) or [`Low`] marker types; use
+[`GarbageCan::throw_away_with_priority`][crate::GarbageCan::throw_away_with_priority]
+to choose a priority at runtime.
+
+# Example
+
+```
+use defer_drop::{priority::High, DeferDrop};
+
+let file: DeferDrop<Vec<u8>, High> = DeferDrop::with_priority(vec![1, 2, 3]);
+drop(file);
+```
+*/
+
+/// The priority of a deferred drop. See the [module documentation][self] for
+/// details.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Priority {
+    /// Dropped before anything else.
+    High,
+
+    /// The default priority.
+    #[default]
+    Normal,
+
+    /// Dropped only when there's no other garbage waiting.
+    Low,
+}
+
+impl Priority {
+    /// The number of priority levels.
+    pub(crate) const COUNT: usize = 3;
+
+    /// All of the priorities, from highest to lowest.
+    pub(crate) const ALL: [Priority; Priority::COUNT] =
+        [Priority::High, Priority::Normal, Priority::Low];
+
+    /// The index of this priority's queue.
+    #[inline]
+    pub(crate) fn index(self) -> usize {
+        self as usize
+    }
+}
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// A priority level, as a type. This is implemented by [`High`], [`Normal`]
+/// and [`Low`], and can't be implemented outside of this crate.
+pub trait Level: sealed::Sealed {
+    /// The priority that this type represents.
+    const PRIORITY: Priority;
+}
+
+macro_rules! levels {
+    ($($(#[$attr:meta])* $name:ident,)*) => {$(
+        $(#[$attr])*
+        #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        pub struct $name;
+
+        impl sealed::Sealed for $name {}
+
+        impl Level for $name {
+            const PRIORITY: Priority = Priority::$name;
+        }
+    )*};
+}
+
+levels! {
+    /// Type-level [`Priority::High`].
+    High,
+    /// Type-level [`Priority::Normal`]. This is the default for
+    /// [`DeferDrop`][crate::DeferDrop].
+    Normal,
+    /// Type-level [`Priority::Low`].
+    Low,
+}
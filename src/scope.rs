@@ -0,0 +1,280 @@
+use std::{
+    cmp::Ordering,
+    fmt,
+    hash::{Hash, Hasher},
+    marker::PhantomData,
+    mem::{self, ManuallyDrop},
+    ops::{Deref, DerefMut},
+    thread,
+};
+
+use crossbeam_channel::{self as channel, Sender};
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+#[cfg(doc)]
+use crate::DeferDrop;
+
+/// A value to drop in a scope's background thread. Unlike the garbage sent
+/// to a [`GarbageCan`][crate::GarbageCan], it doesn't have to be `'static`,
+/// so it can't use the same inline storage.
+type ScopedGarbage<'env> = Box<dyn Send + 'env>;
+
+/// Create a scope with its own background thread, which can drop values that
+/// borrow from outside of the scope.
+///
+/// [`DeferDrop`] needs its values to be `'static`, since there's no telling
+/// when the background thread will get around to dropping them. Inside of a
+/// scope, values only need to outlive the scope itself, because `scope` waits
+/// for its background thread to drop everything that was deferred to it
+/// before returning. Use [`Scope::defer`] to wrap a value, or
+/// [`Scope::throw_away`] to send it right away.
+///
+/// This works like [`std::thread::scope`]; in fact, the background thread is
+/// a scoped thread. If it can't be spawned, values are dropped inline
+/// instead. If a destructor panics in the background thread, `scope` panics
+/// once the closure has returned.
+///
+/// # Example
+///
+/// ```
+/// let words: Vec<String> = vec!["Hello".into(), "World".into()];
+///
+/// let total = defer_drop::scope(|s| {
+///     // This borrows from `words`, so it couldn't be a `DeferDrop`
+///     let lengths = s.defer(words.iter().map(|word| (word, word.len())).collect::<Vec<_>>());
+///
+///     lengths.iter().map(|&(_, len)| len).sum::<usize>()
+/// });
+///
+/// // The vector of lengths has been dropped by now
+/// assert_eq!(total, 10);
+/// ```
+pub fn scope<'env, F, R>(f: F) -> R
+where
+    F: for<'scope> FnOnce(&'scope Scope<'env>) -> R,
+{
+    let (sender, receiver) = channel::unbounded::<ScopedGarbage<'env>>();
+
+    thread::scope(|threads| {
+        let spawned = thread::Builder::new()
+            .name("defer-drop scoped thread".to_owned())
+            .spawn_scoped(threads, move || receiver.into_iter().for_each(drop));
+
+        let scope = Scope {
+            sender: spawned.ok().map(|_| sender),
+            env: PhantomData,
+        };
+
+        // Dropping the scope closes the queue, so the background thread
+        // finishes once it's dropped everything; `thread::scope` joins it
+        // before returning, even if `f` panics.
+        f(&scope)
+    })
+}
+
+/// A scope with a background thread for dropping values, created with
+/// [`scope`]. The values only need to outlive `'env`, the lifetime of the
+/// environment outside of the scope.
+pub struct Scope<'env> {
+    // This is `None` if the background thread couldn't be spawned.
+    sender: Option<Sender<ScopedGarbage<'env>>>,
+
+    // Invariant over `'env`, like `std::thread::Scope`.
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+impl<'env> Scope<'env> {
+    /// Send a value to the scope's background thread to be dropped. If the
+    /// background thread isn't available, it's dropped immediately instead.
+    pub fn throw_away<T: Send + 'env>(&self, value: T) {
+        if let Some(ref sender) = self.sender {
+            // If the background thread is gone (because a destructor
+            // panicked), we get the value back, and drop it here.
+            let _ = sender.send(Box::new(value));
+        }
+    }
+
+    /// Wrap a value in a [`ScopedDeferDrop`], which sends it to this scope's
+    /// background thread when it's dropped.
+    #[inline]
+    pub fn defer<'scope, T: Send + 'env>(
+        &'scope self,
+        value: T,
+    ) -> ScopedDeferDrop<'scope, 'env, T> {
+        ScopedDeferDrop {
+            inner: ManuallyDrop::new(value),
+            scope: self,
+        }
+    }
+}
+
+impl fmt::Debug for Scope<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Scope")
+            .field("pending", &self.sender.as_ref().map_or(0, Sender::len))
+            .finish()
+    }
+}
+
+/// Wrapper type that, when dropped, sends the inner value to a [`Scope`]'s
+/// background thread to be dropped. Create one with [`Scope::defer`].
+///
+/// This works just like [`DeferDrop`], except that the value only has to
+/// outlive the scope, rather than being `'static`.
+pub struct ScopedDeferDrop<'scope, 'env, T: Send + 'env> {
+    inner: ManuallyDrop<T>,
+    scope: &'scope Scope<'env>,
+}
+
+impl<'scope, 'env, T: Send + 'env> ScopedDeferDrop<'scope, 'env, T> {
+    /// Unwrap the `ScopedDeferDrop`, returning the inner value. This has the
+    /// effect of cancelling the deferred drop behavior; ownership of the
+    /// inner value is transferred to the caller.
+    pub fn into_inner(mut this: Self) -> T {
+        let value = unsafe { ManuallyDrop::take(&mut this.inner) };
+        mem::forget(this);
+        value
+    }
+
+    /// Get the [`Scope`] that this value will be sent to.
+    #[inline]
+    pub fn scope(this: &Self) -> &'scope Scope<'env> {
+        this.scope
+    }
+}
+
+impl<'env, T: Send + 'env> Drop for ScopedDeferDrop<'_, 'env, T> {
+    fn drop(&mut self) {
+        self.scope
+            .throw_away(unsafe { ManuallyDrop::take(&mut self.inner) });
+    }
+}
+
+impl<'env, T: Send + 'env> AsRef<T> for ScopedDeferDrop<'_, 'env, T> {
+    #[inline]
+    fn as_ref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<'env, T: Send + 'env> AsMut<T> for ScopedDeferDrop<'_, 'env, T> {
+    #[inline]
+    fn as_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+impl<'env, T: Send + 'env> Deref for ScopedDeferDrop<'_, 'env, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl<'env, T: Send + 'env> DerefMut for ScopedDeferDrop<'_, 'env, T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner
+    }
+}
+
+impl<'env, T: Send + 'env + Clone> Clone for ScopedDeferDrop<'_, 'env, T> {
+    #[inline]
+    fn clone(&self) -> Self {
+        self.scope.defer(T::clone(&self.inner))
+    }
+}
+
+impl<'env, T: Send + 'env + fmt::Debug> fmt::Debug for ScopedDeferDrop<'_, 'env, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ScopedDeferDrop")
+            .field("inner", &*self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'env, T: Send + 'env + PartialEq> PartialEq for ScopedDeferDrop<'_, 'env, T> {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        *self.inner == *other.inner
+    }
+}
+
+impl<'env, T: Send + 'env + Eq> Eq for ScopedDeferDrop<'_, 'env, T> {}
+
+impl<'env, T: Send + 'env + PartialOrd> PartialOrd for ScopedDeferDrop<'_, 'env, T> {
+    #[inline]
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        T::partial_cmp(&self.inner, &other.inner)
+    }
+}
+
+impl<'env, T: Send + 'env + Ord> Ord for ScopedDeferDrop<'_, 'env, T> {
+    #[inline]
+    fn cmp(&self, other: &Self) -> Ordering {
+        T::cmp(&self.inner, &other.inner)
+    }
+}
+
+impl<'env, T: Send + 'env + Hash> Hash for ScopedDeferDrop<'_, 'env, T> {
+    #[inline]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.inner.hash(state)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'env, T: Serialize + Send + 'env> Serialize for ScopedDeferDrop<'_, 'env, T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.as_ref().serialize(serializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Mutex,
+        thread::{self, ThreadId},
+    };
+
+    use super::scope;
+
+    /// Records the thread that dropped it, in a borrowed list.
+    struct BorrowingReporter<'a> {
+        record: &'a Mutex<Vec<ThreadId>>,
+    }
+
+    impl Drop for BorrowingReporter<'_> {
+        fn drop(&mut self) {
+            self.record.lock().unwrap().push(thread::current().id());
+        }
+    }
+
+    #[test]
+    fn test_scope() {
+        let record = Mutex::new(Vec::new());
+
+        let result = scope(|s| {
+            let value = s.defer(BorrowingReporter { record: &record });
+            drop(value);
+
+            s.throw_away(BorrowingReporter { record: &record });
+            "done"
+        });
+
+        assert_eq!(result, "done");
+
+        // Both values were dropped before `scope` returned, in the
+        // background thread
+        let record = record.into_inner().unwrap();
+        assert_eq!(record.len(), 2);
+        assert!(record.iter().all(|&id| id != thread::current().id()));
+    }
+}
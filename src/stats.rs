@@ -0,0 +1,171 @@
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    convert::TryFrom,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, PoisonError,
+    },
+    time::Duration,
+};
+
+/// A snapshot of the activity of a [`GarbageCan`][crate::GarbageCan]. Get one
+/// with [`stats`][crate::stats] (for the global garbage can), or
+/// [`GarbageCan::stats`][crate::GarbageCan::stats].
+///
+/// The counters are updated independently of each other, so a snapshot taken
+/// while garbage is being dropped may be slightly inconsistent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct Stats {
+    /// The number of values that have been sent to the background threads.
+    pub enqueued: u64,
+
+    /// The number of values that the background threads have dropped.
+    pub dropped: u64,
+
+    /// The number of values that are currently waiting to be dropped,
+    /// including any that are being dropped right now.
+    pub queue_len: u64,
+
+    /// The highest `queue_len` that has been observed.
+    pub max_queue_len: u64,
+
+    /// The total time that the background threads have spent in
+    /// destructors.
+    pub drop_time: Duration,
+
+    /// The longest time that the background threads have spent in a single
+    /// destructor.
+    pub max_drop_time: Duration,
+
+    /// The number of values that were dropped inline, on the thread that
+    /// dropped them, because the background threads couldn't accept them:
+    /// the queue was full (with [`Overflow::DropInline`][crate::Overflow]),
+    /// or the background threads were shut down or unavailable.
+    pub inline_drops: u64,
+}
+
+/// The time spent dropping values of a particular type. Get these from
+/// [`type_stats`][crate::type_stats] (for the global garbage can), or
+/// [`GarbageCan::type_stats`][crate::GarbageCan::type_stats], if type
+/// profiling was enabled with
+/// [`Builder::profile_types`][crate::Builder::profile_types].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct TypeStats {
+    /// The name of the type, as reported by [`std::any::type_name`].
+    pub type_name: &'static str,
+
+    /// The number of values of this type that have been dropped.
+    pub dropped: u64,
+
+    /// The total time spent dropping values of this type.
+    pub drop_time: Duration,
+
+    /// The longest time spent dropping a single value of this type.
+    pub max_drop_time: Duration,
+}
+
+/// Per-type drop times, shared by a garbage can's background threads.
+#[derive(Debug, Default)]
+pub(crate) struct Profile {
+    types: Mutex<HashMap<&'static str, TypeStats>>,
+}
+
+impl Profile {
+    pub fn record(&self, type_name: &'static str, elapsed: Duration) {
+        let mut types = self.types.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let stats = types.entry(type_name).or_insert(TypeStats {
+            type_name,
+            dropped: 0,
+            drop_time: Duration::ZERO,
+            max_drop_time: Duration::ZERO,
+        });
+
+        stats.dropped += 1;
+        stats.drop_time += elapsed;
+        stats.max_drop_time = stats.max_drop_time.max(elapsed);
+    }
+
+    pub fn snapshot(&self) -> Vec<TypeStats> {
+        let mut stats: Vec<TypeStats> = self
+            .types
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .values()
+            .copied()
+            .collect();
+
+        stats.sort_by_key(|stats| Reverse(stats.drop_time));
+        stats
+    }
+}
+
+/// The live counters behind [`Stats`], shared by a garbage can and its
+/// background threads.
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    enqueued: AtomicU64,
+    dropped: AtomicU64,
+    max_queue_len: AtomicU64,
+    drop_nanos: AtomicU64,
+    max_drop_nanos: AtomicU64,
+    inline_drops: AtomicU64,
+}
+
+impl Counters {
+    /// Record that some values are about to be sent to the background
+    /// threads. Follow up with `record_sent` or `record_rejected`. The values
+    /// are counted before they're sent so that `dropped` never gets ahead of
+    /// `enqueued`.
+    pub fn record_send(&self, count: u64) {
+        self.enqueued.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Record that values were successfully sent to the background threads.
+    pub fn record_sent(&self) {
+        let enqueued = self.enqueued.load(Ordering::Relaxed);
+        let dropped = self.dropped.load(Ordering::Relaxed);
+
+        self.max_queue_len
+            .fetch_max(enqueued.saturating_sub(dropped), Ordering::Relaxed);
+    }
+
+    /// Record that values couldn't be sent to the background threads after
+    /// all, and were dropped inline instead.
+    pub fn record_rejected(&self, count: u64) {
+        self.enqueued.fetch_sub(count, Ordering::Relaxed);
+        self.record_inline(count);
+    }
+
+    /// Record that values were dropped inline, without being sent.
+    pub fn record_inline(&self, count: u64) {
+        self.inline_drops.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Record that a background thread dropped a value, and how long it took.
+    pub fn record_drop(&self, elapsed: Duration) {
+        let nanos = u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX);
+
+        self.drop_nanos.fetch_add(nanos, Ordering::Relaxed);
+        self.max_drop_nanos.fetch_max(nanos, Ordering::Relaxed);
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> Stats {
+        let dropped = self.dropped.load(Ordering::Relaxed);
+        let enqueued = self.enqueued.load(Ordering::Relaxed);
+
+        Stats {
+            enqueued,
+            dropped,
+            queue_len: enqueued.saturating_sub(dropped),
+            max_queue_len: self.max_queue_len.load(Ordering::Relaxed),
+            drop_time: Duration::from_nanos(self.drop_nanos.load(Ordering::Relaxed)),
+            max_drop_time: Duration::from_nanos(self.max_drop_nanos.load(Ordering::Relaxed)),
+            inline_drops: self.inline_drops.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,83 @@
+//! Helpers shared by the tests of several modules.
+
+use crossbeam_channel as channel;
+use std::{
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use crate::GarbageCan;
+
+/// This struct, when dropped, reports the thread ID of its dropping thread to
+/// the channel
+pub(crate) struct ThreadReporter {
+    pub chan: channel::Sender<thread::ThreadId>,
+}
+
+impl Drop for ThreadReporter {
+    fn drop(&mut self) {
+        self.chan.send(thread::current().id()).unwrap();
+    }
+}
+
+/// A shared list of IDs, in the order that their [`Recorder`]s were dropped.
+pub(crate) type Record<I> = Arc<Mutex<Vec<I>>>;
+
+/// This struct, when dropped, adds its ID to a [`Record`], and then drops
+/// its children, if it has any.
+pub(crate) struct Recorder<I: Copy, T = ()> {
+    id: I,
+    record: Record<I>,
+    _children: T,
+}
+
+impl<I: Copy> Recorder<I> {
+    pub fn new(id: I, record: &Record<I>) -> Self {
+        Self::with_children(id, record, ())
+    }
+}
+
+impl<I: Copy, T> Recorder<I, T> {
+    pub fn with_children(id: I, record: &Record<I>, children: T) -> Self {
+        Self {
+            id,
+            record: record.clone(),
+            _children: children,
+        }
+    }
+}
+
+impl<I: Copy, T> Drop for Recorder<I, T> {
+    fn drop(&mut self) {
+        self.record.lock().unwrap().push(self.id);
+    }
+}
+
+/// This struct, when dropped, announces that it's being dropped and then
+/// blocks until it's released. Used to stall a background thread.
+struct Stall {
+    started: channel::Sender<()>,
+    release: channel::Receiver<()>,
+}
+
+impl Drop for Stall {
+    fn drop(&mut self) {
+        self.started.send(()).unwrap();
+        let _ = self.release.recv();
+    }
+}
+
+/// Stall one of the garbage can's background threads, and wait until it's
+/// stalled. Drop the returned sender to un-stall it.
+pub(crate) fn stall(can: &GarbageCan) -> channel::Sender<()> {
+    let (started_send, started) = channel::bounded(1);
+    let (release, release_recv) = channel::bounded(0);
+
+    can.throw_away(Stall {
+        started: started_send,
+        release: release_recv,
+    });
+    started.recv().unwrap();
+
+    release
+}
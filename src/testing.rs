@@ -0,0 +1,406 @@
+use std::{
+    cell::{Cell, RefCell},
+    fmt,
+    marker::PhantomData,
+    mem,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError, RwLock,
+    },
+};
+
+use crate::{executor::DropExecutor, garbage::Garbage};
+
+#[cfg(doc)]
+use crate::{DeferDrop, GarbageCan};
+
+/// The number of test executors that are currently installed or running,
+/// anywhere. While this is zero, [`interceptor`] doesn't need to look any
+/// further.
+static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+
+/// Test executors installed for every thread, most recent last.
+static GLOBAL: RwLock<Vec<TestExecutor>> = RwLock::new(Vec::new());
+
+thread_local! {
+    /// Test executors installed for this thread, most recent last.
+    static LOCAL: RefCell<Vec<TestExecutor>> = const { RefCell::new(Vec::new()) };
+
+    /// True while this thread is running a test executor's pending drops.
+    static RUNNING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Where to send a value instead of a garbage can, while a [`TestExecutor`]
+/// is active.
+pub(crate) enum Interceptor {
+    /// Drop it inline, because this thread is running pending drops.
+    Inline,
+
+    /// Hold on to it in this executor.
+    Executor(TestExecutor),
+}
+
+impl Interceptor {
+    pub fn take(self, garbage: Garbage) {
+        match self {
+            Interceptor::Inline => drop(garbage),
+            Interceptor::Executor(executor) => executor.execute(garbage),
+        }
+    }
+}
+
+/// Get the interceptor for values that this thread sends to a garbage can,
+/// if a [`TestExecutor`] is installed or running.
+pub(crate) fn interceptor() -> Option<Interceptor> {
+    if ACTIVE.load(Ordering::Acquire) == 0 {
+        return None;
+    }
+
+    // Values deferred while running the pending drops are dropped inline,
+    // just like they would be in a background thread.
+    if RUNNING.try_with(Cell::get).unwrap_or(false) {
+        return Some(Interceptor::Inline);
+    }
+
+    LOCAL
+        .try_with(|local| local.borrow().last().cloned())
+        .ok()
+        .flatten()
+        .or_else(|| {
+            GLOBAL
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+                .last()
+                .cloned()
+        })
+        .map(Interceptor::Executor)
+}
+
+/// A [`DropExecutor`] for tests, which holds on to everything deferred to it
+/// until [`run_pending`][TestExecutor::run_pending] is called. Requires the
+/// `test-executor` feature.
+///
+/// Testing code that uses [`DeferDrop`] normally means waiting for the
+/// background thread, with [`flush`][crate::flush] or a timeout. Instead, a
+/// test can [`install`][TestExecutor::install] a `TestExecutor`, which
+/// captures everything that its thread sends to a [`GarbageCan`] (including
+/// the global one used by [`DeferDrop`]), and then check what's pending and
+/// drop it at a known point, on the test's own thread.
+///
+/// Delayed drops (like [`DeferDrop::drop_after`]) are captured too; their
+/// deadlines are ignored.
+///
+/// Clones of a `TestExecutor` share the same pending values.
+///
+/// # Example
+///
+/// ```
+/// use defer_drop::{DeferDrop, TestExecutor};
+/// use std::any::type_name;
+///
+/// let executor = TestExecutor::new();
+/// let _guard = executor.install();
+///
+/// drop(DeferDrop::new(vec![1, 2, 3]));
+/// drop(DeferDrop::new(String::from("Hello")));
+///
+/// assert_eq!(executor.pending(), 2);
+/// assert_eq!(
+///     executor.pending_type_names(),
+///     [type_name::<Vec<i32>>(), type_name::<String>()],
+/// );
+///
+/// assert_eq!(executor.run_pending(), 2);
+/// assert_eq!(executor.pending(), 0);
+/// ```
+#[derive(Clone, Default)]
+pub struct TestExecutor {
+    pending: Arc<Mutex<Vec<Garbage>>>,
+}
+
+impl TestExecutor {
+    /// Create a new `TestExecutor`, with nothing pending.
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<Garbage>> {
+        self.pending.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Install this executor for the current thread, until the returned guard
+    /// is dropped. While it's installed, values that this thread sends to any
+    /// [`GarbageCan`] are held by this executor instead.
+    ///
+    /// Installations nest: the most recently installed executor is used.
+    pub fn install(&self) -> TestGuard {
+        LOCAL.with(|local| local.borrow_mut().push(self.clone()));
+        ACTIVE.fetch_add(1, Ordering::AcqRel);
+
+        TestGuard {
+            executor: self.clone(),
+            global: false,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Install this executor for every thread, until the returned guard is
+    /// dropped. An executor installed with [`install`][TestExecutor::install]
+    /// takes precedence on its own thread.
+    ///
+    /// Since tests usually run in parallel, in the same process, prefer
+    /// [`install`][TestExecutor::install] unless the code being tested
+    /// defers drops from threads of its own.
+    ///
+    /// ```
+    /// use defer_drop::{DeferDrop, TestExecutor};
+    /// use std::thread;
+    ///
+    /// let executor = TestExecutor::new();
+    /// let _guard = executor.install_global();
+    ///
+    /// thread::spawn(|| drop(DeferDrop::new(vec![1, 2, 3])))
+    ///     .join()
+    ///     .unwrap();
+    ///
+    /// assert_eq!(executor.pending(), 1);
+    /// ```
+    pub fn install_global(&self) -> TestGuard {
+        GLOBAL
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(self.clone());
+        ACTIVE.fetch_add(1, Ordering::AcqRel);
+
+        TestGuard {
+            executor: self.clone(),
+            global: true,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// The number of values waiting to be dropped.
+    pub fn pending(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// The type names of the values waiting to be dropped, in the order they
+    /// were deferred, as reported by [`std::any::type_name`].
+    pub fn pending_type_names(&self) -> Vec<&'static str> {
+        self.lock().iter().map(Garbage::type_name).collect()
+    }
+
+    /// Drop all of the pending values on the current thread, in the order
+    /// they were deferred, and return how many there were. Any values that
+    /// they defer in turn are dropped inline, just like in a background
+    /// thread.
+    pub fn run_pending(&self) -> usize {
+        let pending = mem::take(&mut *self.lock());
+        let count = pending.len();
+
+        let _running = Running::start();
+        drop(pending);
+
+        count
+    }
+}
+
+impl DropExecutor for TestExecutor {
+    fn execute(&self, garbage: Garbage) {
+        self.lock().push(garbage);
+    }
+}
+
+impl fmt::Debug for TestExecutor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TestExecutor")
+            .field("pending", &self.pending_type_names())
+            .finish()
+    }
+}
+
+/// Marks the current thread as running pending drops, until it's dropped.
+struct Running {
+    // Whether this thread was already running; `run_pending` might be called
+    // from inside a pending drop.
+    nested: bool,
+}
+
+impl Running {
+    fn start() -> Self {
+        ACTIVE.fetch_add(1, Ordering::AcqRel);
+
+        Self {
+            nested: RUNNING.with(|running| running.replace(true)),
+        }
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        RUNNING.with(|running| running.set(self.nested));
+        ACTIVE.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Guard that uninstalls a [`TestExecutor`] when it's dropped. Create one
+/// with [`TestExecutor::install`] or [`TestExecutor::install_global`].
+///
+/// Values that are still pending in the executor aren't affected.
+#[must_use = "the test executor is uninstalled when the TestGuard is dropped"]
+pub struct TestGuard {
+    executor: TestExecutor,
+    global: bool,
+
+    // A thread-local installation has to be removed from the same thread.
+    _not_send: PhantomData<Rc<()>>,
+}
+
+impl Drop for TestGuard {
+    fn drop(&mut self) {
+        let remove = |installed: &mut Vec<TestExecutor>| {
+            if let Some(index) = installed
+                .iter()
+                .rposition(|executor| Arc::ptr_eq(&executor.pending, &self.executor.pending))
+            {
+                installed.remove(index);
+            }
+        };
+
+        match self.global {
+            true => remove(&mut GLOBAL.write().unwrap_or_else(PoisonError::into_inner)),
+            false => {
+                let _ = LOCAL.try_with(|local| remove(&mut local.borrow_mut()));
+            }
+        }
+
+        ACTIVE.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl fmt::Debug for TestGuard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TestGuard")
+            .field("global", &self.global)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread};
+
+    use super::TestExecutor;
+    use crate::{
+        test_util::{Record, Recorder},
+        DeferDrop, GarbageCan,
+    };
+
+    #[test]
+    fn test_run_pending() {
+        let executor = TestExecutor::new();
+        let guard = executor.install();
+
+        let value = Arc::new(());
+        drop(DeferDrop::new(value.clone()));
+
+        assert_eq!(executor.pending(), 1);
+        assert_eq!(Arc::strong_count(&value), 2);
+
+        assert_eq!(executor.run_pending(), 1);
+        assert_eq!(Arc::strong_count(&value), 1);
+
+        // Once it's uninstalled, values go to the background thread again
+        drop(guard);
+        drop(DeferDrop::new(value.clone()));
+        crate::flush();
+
+        assert_eq!(executor.pending(), 0);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn test_other_threads() {
+        let executor = TestExecutor::new();
+        let _guard = executor.install();
+
+        // Other threads aren't affected
+        let value = Arc::new(());
+        let clone = value.clone();
+        thread::spawn(move || drop(DeferDrop::new(clone)))
+            .join()
+            .unwrap();
+        crate::flush();
+
+        assert_eq!(executor.pending(), 0);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn test_garbage_can() {
+        let executor = TestExecutor::new();
+        let _guard = executor.install();
+
+        let can = GarbageCan::new();
+        can.throw_away(String::from("Hello"));
+        can.throw_away_incremental(vec![1, 2, 3]);
+
+        assert_eq!(
+            executor.pending_type_names(),
+            [
+                std::any::type_name::<String>(),
+                std::any::type_name::<Vec<i32>>()
+            ]
+        );
+        assert_eq!(can.stats().enqueued, 0);
+    }
+
+    #[test]
+    fn test_handle_type_name() {
+        let executor = TestExecutor::new();
+        let _guard = executor.install();
+
+        // Reported as the value's own type, not the internal wrapper
+        let _handle = DeferDrop::drop_with_handle(DeferDrop::new(vec![1, 2, 3]));
+
+        assert_eq!(
+            executor.pending_type_names(),
+            [std::any::type_name::<Vec<i32>>()]
+        );
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_parallel_type_name() {
+        let executor = TestExecutor::new();
+        let _guard = executor.install();
+
+        GarbageCan::new().throw_away_parallel(vec![String::new()]);
+
+        assert_eq!(
+            executor.pending_type_names(),
+            [std::any::type_name::<Vec<String>>()]
+        );
+    }
+
+    #[test]
+    fn test_nested_order() {
+        let executor = TestExecutor::new();
+        let _guard = executor.install();
+        let record: Record<u32> = Default::default();
+
+        let leaf = |id| DeferDrop::new(Recorder::new(id, &record));
+        drop(DeferDrop::new(Recorder::with_children(
+            0,
+            &record,
+            [leaf(1), leaf(2)],
+        )));
+
+        assert_eq!(executor.pending(), 1);
+        assert_eq!(executor.run_pending(), 1);
+        assert_eq!(executor.pending(), 0);
+        assert_eq!(record.lock().unwrap().as_slice(), [0, 1, 2]);
+    }
+}
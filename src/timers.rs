@@ -0,0 +1,131 @@
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::BinaryHeap,
+    mem,
+    sync::{Mutex, MutexGuard, PoisonError},
+    time::Instant,
+};
+
+use crate::garbage::Garbage;
+
+/// A value that shouldn't be dropped until its deadline.
+struct Timer {
+    deadline: Instant,
+
+    // Timers with the same deadline are dropped in the order they were
+    // added.
+    seq: u64,
+    garbage: Garbage,
+}
+
+impl Timer {
+    fn key(&self) -> (Instant, u64) {
+        (self.deadline, self.seq)
+    }
+}
+
+impl PartialEq for Timer {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Timer {}
+
+impl PartialOrd for Timer {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Timer {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+#[derive(Default)]
+struct State {
+    heap: BinaryHeap<Reverse<Timer>>,
+    next_seq: u64,
+}
+
+/// Delayed garbage, shared by a garbage can's background threads, so that
+/// it isn't lost if one of them is replaced after a panic.
+#[derive(Default)]
+pub(crate) struct Timers {
+    state: Mutex<State>,
+}
+
+impl Timers {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn insert(&self, deadline: Instant, garbage: Garbage) {
+        let mut state = self.lock();
+        let seq = state.next_seq;
+        state.next_seq += 1;
+
+        state.heap.push(Reverse(Timer {
+            deadline,
+            seq,
+            garbage,
+        }));
+    }
+
+    /// The earliest deadline, if there are any timers.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.lock().heap.peek().map(|timer| timer.0.deadline)
+    }
+
+    /// Remove all of the timers whose deadlines have passed, in order.
+    pub fn take_expired(&self, now: Instant) -> Vec<Garbage> {
+        let mut state = self.lock();
+        let mut expired = Vec::new();
+
+        while matches!(state.heap.peek(), Some(timer) if timer.0.deadline <= now) {
+            if let Some(Reverse(timer)) = state.heap.pop() {
+                expired.push(timer.garbage);
+            }
+        }
+
+        expired
+    }
+
+    /// Remove all of the timers, in order, regardless of their deadlines.
+    pub fn take_all(&self) -> Vec<Garbage> {
+        let heap = mem::take(&mut self.lock().heap);
+
+        heap.into_sorted_vec()
+            .into_iter()
+            .rev()
+            .map(|timer| timer.0.garbage)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::Timers;
+    use crate::garbage::Garbage;
+
+    #[test]
+    fn test_deadlines() {
+        let timers = Timers::default();
+        let now = Instant::now();
+
+        for &(offset, name) in &[(2, "c"), (1, "a"), (1, "b"), (3, "d")] {
+            timers.insert(now + Duration::from_secs(offset), Garbage::new(name));
+        }
+
+        assert_eq!(timers.next_deadline(), Some(now + Duration::from_secs(1)));
+        assert!(timers.take_expired(now).is_empty());
+        assert_eq!(timers.take_expired(now + Duration::from_secs(2)).len(), 3);
+        assert_eq!(timers.next_deadline(), Some(now + Duration::from_secs(3)));
+        assert_eq!(timers.take_all().len(), 1);
+        assert_eq!(timers.next_deadline(), None);
+    }
+}
@@ -0,0 +1,232 @@
+/*!
+Deferred drops on the [tokio] blocking thread pool, rather than a dedicated
+background thread. Requires the `tokio` feature.
+
+Inside a tokio runtime, a [`TokioDeferDrop`] hands its value to
+[`spawn_blocking`][::tokio::task::spawn_blocking] when it's dropped, so the
+drop is run (and instrumented) by the runtime like any other blocking task.
+Outside of a runtime, it falls back to the global garbage can, just like a
+[`DeferDrop`][crate::DeferDrop].
+*/
+
+use std::{
+    mem::{self, ManuallyDrop},
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use ::tokio::{runtime::Handle, sync::Notify};
+use once_cell::sync::Lazy;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::global_garbage_can;
+
+/// The number of values that have been handed to the blocking pool and not
+/// dropped yet, and a notification for when that reaches zero.
+#[derive(Default)]
+struct InFlight {
+    count: AtomicUsize,
+    idle: Notify,
+}
+
+static IN_FLIGHT: Lazy<InFlight> = Lazy::new(Default::default);
+
+/// Decrements the in-flight count when it's dropped, even if the value's
+/// destructor panics.
+struct Landing;
+
+impl Drop for Landing {
+    fn drop(&mut self) {
+        if IN_FLIGHT.count.fetch_sub(1, Ordering::AcqRel) == 1 {
+            IN_FLIGHT.idle.notify_waiters();
+        }
+    }
+}
+
+/// Drop a value on the current runtime's blocking pool, or in the global
+/// garbage can if there is no current runtime.
+fn spawn_drop<T: Send + 'static>(value: T) {
+    let handle = match Handle::try_current() {
+        Ok(handle) => handle,
+        Err(_) => {
+            global_garbage_can().throw_away(value);
+            return;
+        }
+    };
+
+    IN_FLIGHT.count.fetch_add(1, Ordering::AcqRel);
+    let landing = Landing;
+
+    // If the runtime is shutting down, the task is never run, and the
+    // closure (along with the value) is dropped wherever tokio drops it.
+    handle.spawn_blocking(move || {
+        let _landing = landing;
+        drop(value);
+    });
+}
+
+/// Wait until every value that has been handed to the blocking pool by a
+/// [`TokioDeferDrop`] has been dropped.
+///
+/// This waits until there are no deferred drops in flight at all, so if
+/// other tasks keep dropping values, it may wait for those too. Values that
+/// went to the global garbage can, because they were dropped outside of a
+/// runtime, aren't included; use [`flush`][crate::flush] for those.
+pub async fn flush() {
+    loop {
+        let idle = IN_FLIGHT.idle.notified();
+
+        if IN_FLIGHT.count.load(Ordering::Acquire) == 0 {
+            return;
+        }
+
+        idle.await;
+    }
+}
+
+/// Wrapper type that, when dropped, sends the inner value to the current
+/// tokio runtime's blocking thread pool to be dropped. If it's dropped
+/// outside of a runtime, the value is sent to the global garbage can
+/// instead.
+///
+/// This is otherwise identical to [`DeferDrop`][crate::DeferDrop]; see its
+/// documentation for details.
+///
+/// # Example
+///
+/// ```
+/// use defer_drop::tokio::TokioDeferDrop;
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let value = TokioDeferDrop::new(vec![String::from("Hello"); 1000]);
+/// drop(value);
+///
+/// defer_drop::tokio::flush().await;
+/// # }
+/// ```
+#[repr(transparent)]
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TokioDeferDrop<T: Send + 'static> {
+    inner: ManuallyDrop<T>,
+}
+
+impl<T: Send + 'static> TokioDeferDrop<T> {
+    /// Create a new `TokioDeferDrop` value.
+    #[inline]
+    pub fn new(value: T) -> Self {
+        TokioDeferDrop {
+            inner: ManuallyDrop::new(value),
+        }
+    }
+
+    /// Unwrap the `TokioDeferDrop`, returning the inner value. This has the
+    /// effect of cancelling the deferred drop behavior; ownership of the
+    /// inner value is transferred to the caller.
+    pub fn into_inner(mut this: Self) -> T {
+        let value = unsafe { ManuallyDrop::take(&mut this.inner) };
+        mem::forget(this);
+        value
+    }
+}
+
+impl<T: Send + 'static> Drop for TokioDeferDrop<T> {
+    fn drop(&mut self) {
+        spawn_drop(unsafe { ManuallyDrop::take(&mut self.inner) });
+    }
+}
+
+impl<T: Send + 'static> From<T> for TokioDeferDrop<T> {
+    #[inline]
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<T: Send + 'static> AsRef<T> for TokioDeferDrop<T> {
+    #[inline]
+    fn as_ref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T: Send + 'static> AsMut<T> for TokioDeferDrop<T> {
+    #[inline]
+    fn as_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+impl<T: Send + 'static> Deref for TokioDeferDrop<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl<T: Send + 'static> DerefMut for TokioDeferDrop<T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T: Serialize + Send + 'static> Serialize for TokioDeferDrop<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.as_ref().serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T: Deserialize<'de> + Send + 'static> Deserialize<'de> for TokioDeferDrop<T> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        T::deserialize(deserializer).map(Self::new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    use super::{flush, TokioDeferDrop};
+
+    /// Records the name of the thread that dropped it.
+    struct NameReporter(Arc<Mutex<Option<String>>>);
+
+    impl Drop for NameReporter {
+        fn drop(&mut self) {
+            *self.0.lock().unwrap() = thread::current().name().map(str::to_owned);
+        }
+    }
+
+    #[test]
+    fn test_spawn_blocking() {
+        let runtime = ::tokio::runtime::Builder::new_current_thread()
+            .thread_name("tokio blocking")
+            .build()
+            .unwrap();
+
+        let name: Arc<Mutex<Option<String>>> = Default::default();
+
+        runtime.block_on(async {
+            drop(TokioDeferDrop::new(NameReporter(name.clone())));
+            flush().await;
+        });
+
+        assert_eq!(name.lock().unwrap().as_deref(), Some("tokio blocking"));
+    }
+}